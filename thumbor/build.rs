@@ -1,6 +1,36 @@
+// 所有的 message，JSON 里没有写的字段和 protobuf 一样使用默认值
+// 不以 `.` 开头的路径按后缀匹配，不会匹配到嵌套的 enum 和 oneof
+const MESSAGES: &[&str] = &[
+    "abi.ImageSpec",
+    "abi.Resize",
+    "abi.Crop",
+    "abi.SmartCrop",
+    "abi.FocalCrop",
+    "abi.Fliph",
+    "abi.Flipv",
+    "abi.Contrast",
+    "abi.Filter",
+    "abi.Watermark",
+    "abi.Watermark.Text",
+    "abi.Format",
+    "abi.Brightness",
+    "abi.Saturation",
+    "abi.HueRotate",
+    "abi.Blur",
+    "abi.Sharpen",
+    "abi.Grayscale",
+    "abi.Rotate",
+    "abi.Spec",
+];
+
 fn main() {
-    prost_build::Config::new()
+    let mut config = prost_build::Config::new();
+    config
         .out_dir("src/pb")
-        .compile_protos(&["abi.proto"], &["."])
-        .unwrap()
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".", "#[serde(rename_all = \"snake_case\")]");
+    for message in MESSAGES {
+        config.type_attribute(message, "#[serde(default)]");
+    }
+    config.compile_protos(&["abi.proto"], &["."]).unwrap()
 }
@@ -0,0 +1,13 @@
+mod disk;
+mod flight;
+mod key;
+mod memory;
+mod policy;
+mod tiered;
+
+pub use disk::DiskCache;
+pub use flight::SingleFlight;
+pub use key::CacheKey;
+pub use memory::{CacheStats, MemoryCache};
+pub use policy::ttl_from_headers;
+pub use tiered::{TierStats, TieredCache};
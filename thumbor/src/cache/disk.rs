@@ -0,0 +1,315 @@
+use super::{CacheKey, CacheStats};
+use bytes::Bytes;
+use lru::LruCache;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+// 缓存文件开头的 16 个字节是过期时间（unix 秒）和数据的长度，都是大端的 u64
+const HEADER_LEN: usize = 16;
+// 写入中的临时文件后缀，启动时会被清理
+const TMP_EXTENSION: &str = "tmp";
+
+// 同一个 key 可能被同时写入，每次写入都使用不同的临时文件
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+struct Index {
+    // key -> 文件大小，按照最近使用排序
+    entries: LruCache<CacheKey, u64>,
+    stats: CacheStats,
+}
+
+impl Index {
+    fn insert(&mut self, key: CacheKey, len: u64) {
+        if let Some(old) = self.entries.put(key, len) {
+            self.stats.bytes -= old as usize;
+        }
+        self.stats.bytes += len as usize;
+        self.stats.entries = self.entries.len();
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        let removed = match self.entries.pop(key) {
+            Some(len) => {
+                self.stats.bytes -= len as usize;
+                true
+            }
+            None => false,
+        };
+        self.stats.entries = self.entries.len();
+        removed
+    }
+
+    // 淘汰最久未使用的条目直到总大小不超过 max_bytes，返回需要删除的文件
+    fn evict(&mut self, max_bytes: u64) -> Vec<CacheKey> {
+        let mut evicted = Vec::new();
+        while self.stats.bytes as u64 > max_bytes {
+            match self.entries.pop_lru() {
+                Some((key, len)) => {
+                    self.stats.bytes -= len as usize;
+                    self.stats.evictions += 1;
+                    evicted.push(key);
+                }
+                None => break,
+            }
+        }
+        self.stats.entries = self.entries.len();
+        evicted
+    }
+}
+
+// 磁盘缓存：每个条目是 dir 下的一个文件，按照总字节数做 LRU 淘汰
+// 启动时扫描目录重建索引，所以重启之后缓存依然有效
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    // 索引只在内存里做简单的更新，不要跨 await 持有
+    index: Mutex<Index>,
+}
+
+impl DiskCache {
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let meta = fs::metadata(&path)?;
+            if !meta.is_file() {
+                continue;
+            }
+            match parse_key(&path) {
+                Some(key) => files.push((key, meta.len(), meta.modified()?)),
+                // 上次没写完的临时文件
+                None if path.extension() == Some(OsStr::new(TMP_EXTENSION)) => {
+                    let _ = fs::remove_file(&path);
+                }
+                // 不认识的文件不去动它
+                None => {}
+            }
+        }
+        // 按照修改时间排序，最近写入的最后插入，也就成为了最近使用的条目
+        files.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut index = Index {
+            entries: LruCache::unbounded(),
+            stats: CacheStats::default(),
+        };
+        for (key, len, _) in files {
+            index.insert(key, len);
+        }
+        let cache = Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+        };
+        // 配置的大小可能比上次运行时小
+        let evicted = cache.index.lock().unwrap().evict(max_bytes);
+        for key in evicted {
+            let _ = fs::remove_file(cache.path(&key));
+        }
+        Ok(cache)
+    }
+
+    // 返回缓存的数据以及剩余的有效时间
+    pub async fn get(&self, key: &CacheKey) -> Option<(Bytes, Duration)> {
+        let known = self.index.lock().unwrap().entries.get(key).is_some();
+        if !known {
+            self.index.lock().unwrap().stats.misses += 1;
+            return None;
+        }
+
+        let entry = match tokio::fs::read(self.path(key)).await {
+            Ok(buf) => decode(buf),
+            Err(e) => {
+                warn!("Failed to read disk cache {}: {}", key, e);
+                None
+            }
+        };
+        match entry {
+            Some(entry) => {
+                self.index.lock().unwrap().stats.hits += 1;
+                Some(entry)
+            }
+            None => {
+                // 过期或者损坏的条目直接删掉
+                let removed = {
+                    let mut index = self.index.lock().unwrap();
+                    index.stats.misses += 1;
+                    index.stats.expirations += 1;
+                    index.remove(key)
+                };
+                if removed {
+                    let _ = tokio::fs::remove_file(self.path(key)).await;
+                }
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: CacheKey, data: &Bytes, ttl: Duration) {
+        let len = (HEADER_LEN + data.len()) as u64;
+        if len > self.max_bytes {
+            return;
+        }
+
+        let expires_at = (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut buf = Vec::with_capacity(len as usize);
+        buf.extend_from_slice(&expires_at.to_be_bytes());
+        buf.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        buf.extend_from_slice(data);
+
+        // 先写临时文件再 rename，避免读到写了一半的文件
+        let path = self.path(&key);
+        let tmp = path.with_extension(format!(
+            "{}-{}.{}",
+            process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            TMP_EXTENSION
+        ));
+        let written = match tokio::fs::write(&tmp, buf).await {
+            Ok(()) => tokio::fs::rename(&tmp, &path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Failed to write disk cache {}: {}", key, e);
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return;
+        }
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(key, len);
+            index.evict(self.max_bytes)
+        };
+        for key in evicted {
+            let _ = tokio::fs::remove_file(self.path(&key)).await;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.index.lock().unwrap().stats
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(key.to_hex())
+    }
+}
+
+fn parse_key(path: &Path) -> Option<CacheKey> {
+    CacheKey::from_hex(path.file_name()?.to_str()?)
+}
+
+// 解析缓存文件，过期、长度不对（比如被截断）或者格式不对时返回 None
+fn decode(buf: Vec<u8>) -> Option<(Bytes, Duration)> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+    let expires_at = u64::from_be_bytes(buf[..8].try_into().ok()?);
+    let len = u64::from_be_bytes(buf[8..HEADER_LEN].try_into().ok()?);
+    if len != (buf.len() - HEADER_LEN) as u64 {
+        return None;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    if expires_at <= now {
+        return None;
+    }
+    let data = Bytes::from(buf).slice(HEADER_LEN..);
+    Some((data, Duration::from_secs(expires_at - now)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn key(n: u32) -> CacheKey {
+        CacheKey::source(&format!("https://example.com/{}.jpg", n))
+    }
+
+    #[tokio::test]
+    async fn disk_cache_should_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 1024).unwrap();
+        cache.put(key(1), &Bytes::from_static(b"hello"), TTL).await;
+        drop(cache);
+
+        let cache = DiskCache::open(dir.path(), 1024).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+        let (data, ttl) = cache.get(&key(1)).await.unwrap();
+        assert_eq!(data, Bytes::from_static(b"hello"));
+        assert!(ttl <= TTL);
+    }
+
+    #[tokio::test]
+    async fn disk_cache_should_evict_by_total_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        // 每个条目 16 字节的头加上 4 字节的数据，最多放两个
+        let cache = DiskCache::open(dir.path(), 50).unwrap();
+        cache.put(key(1), &Bytes::from_static(b"1111"), TTL).await;
+        cache.put(key(2), &Bytes::from_static(b"2222"), TTL).await;
+        assert!(cache.get(&key(1)).await.is_some());
+        cache.put(key(3), &Bytes::from_static(b"3333"), TTL).await;
+
+        assert!(cache.get(&key(2)).await.is_none());
+        assert!(cache.get(&key(1)).await.is_some());
+        assert!(cache.get(&key(3)).await.is_some());
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(!dir.path().join(key(2).to_hex()).exists());
+    }
+
+    #[tokio::test]
+    async fn expired_entry_should_be_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 1024).unwrap();
+        cache
+            .put(key(1), &Bytes::from_static(b"hello"), Duration::ZERO)
+            .await;
+        assert!(cache.get(&key(1)).await.is_none());
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().expirations, 1);
+    }
+
+    #[tokio::test]
+    async fn truncated_entry_should_be_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 1024).unwrap();
+        cache.put(key(1), &Bytes::from_static(b"hello"), TTL).await;
+        let path = dir.path().join(key(1).to_hex());
+        let buf = fs::read(&path).unwrap();
+        fs::write(&path, &buf[..buf.len() - 2]).unwrap();
+
+        assert!(cache.get(&key(1)).await.is_none());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_puts_should_not_share_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path(), 1 << 20).unwrap();
+        let small = Bytes::from(vec![1; 10]);
+        let large = Bytes::from(vec![2; 100_000]);
+        futures::join!(
+            cache.put(key(1), &small, TTL),
+            cache.put(key(1), &large, TTL),
+        );
+        let (data, _) = cache.get(&key(1)).await.unwrap();
+        assert!(data == small || data == large);
+        // 临时文件都已经被 rename 掉
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
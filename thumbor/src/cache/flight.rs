@@ -0,0 +1,106 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+type Call<V, E> = Shared<BoxFuture<'static, Result<V, E>>>;
+
+// 合并相同 key 的并发请求（single-flight）：同一时间只有一个请求真正去执行，
+// 其他请求等待并共享它的结果；不同 key 之间互不影响
+pub struct SingleFlight<K, V, E> {
+    calls: Arc<Mutex<HashMap<K, Call<V, E>>>>,
+}
+
+impl<K, V, E> Default for SingleFlight<K, V, E> {
+    fn default() -> Self {
+        Self {
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<K, V, E> SingleFlight<K, V, E>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>> + Send + 'static,
+    {
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(call) => call.clone(),
+                None => {
+                    let fut = f();
+                    let calls_ref = self.calls.clone();
+                    let k = key.clone();
+                    // 执行结束后自己从表中移除，之后的请求会重新执行
+                    // 即使发起的请求被取消，只要还有人在等待，future 就会继续被执行
+                    let call = async move {
+                        let result = fut.await;
+                        calls_ref.lock().unwrap().remove(&k);
+                        result
+                    }
+                    .boxed()
+                    .shared();
+                    calls.insert(key, call.clone());
+                    call
+                }
+            }
+        };
+        call.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn concurrent_calls_should_be_coalesced() {
+        let flight = Arc::new(SingleFlight::<u64, u64, String>::default());
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = Vec::new();
+        for _ in 0..10 {
+            let flight = flight.clone();
+            let count = count.clone();
+            handles.push(tokio::spawn(async move {
+                flight
+                    .run(1, || async move {
+                        count.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok(42)
+                    })
+                    .await
+                    .unwrap()
+            }));
+        }
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), 42);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // 完成之后再次调用会重新执行
+        flight.run(1, || async { Ok(43) }).await.unwrap();
+        assert_eq!(flight.run(1, || async { Ok(44) }).await.unwrap(), 44);
+    }
+
+    #[tokio::test]
+    async fn errors_should_be_shared() {
+        let flight = SingleFlight::<u64, u64, String>::default();
+        let result = flight.run(1, || async { Err("boom".to_owned()) }).await;
+        assert_eq!(result, Err("boom".to_owned()));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{format::OutputFormat, pb::ImageSpec};
+use prost::Message;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// 缓存的 key：对规范化之后的 url、spec 等内容做 SHA-256
+// 结果和 rust 版本、进程无关，可以同时用于内存缓存、磁盘缓存的文件名以及日志
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    // 原图的 key
+    pub fn source(url: &str) -> Self {
+        let mut hasher = KeyHasher::new("source");
+        hasher.field(normalize_url(url).as_bytes());
+        hasher.finish()
+    }
+
+    // 缩略图的 key：同样的原图、spec 和输出格式，生成的缩略图一定是一样的
+    pub fn thumbnail(url: &str, spec: &ImageSpec, format: OutputFormat) -> Self {
+        let mut hasher = KeyHasher::new("thumbnail");
+        hasher.field(normalize_url(url).as_bytes());
+        // protobuf 编码对同样的 spec 是确定的，不受 url 里 base64 写法的影响
+        hasher.field(&spec.encode_to_vec());
+        hasher.field(format.content_type().as_bytes());
+        if let OutputFormat::Jpeg(quality) = format {
+            hasher.field(&[quality]);
+        }
+        hasher.finish()
+    }
+
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let mut key = [0u8; 32];
+        hex::decode_to_slice(s, &mut key).ok()?;
+        Some(Self(key))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CacheKey({})", self.to_hex())
+    }
+}
+
+// 每个字段前面加上长度，避免不同字段拼接之后产生歧义
+struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    fn new(kind: &str) -> Self {
+        let mut hasher = Self(Sha256::new());
+        hasher.field(kind.as_bytes());
+        hasher
+    }
+
+    fn field(&mut self, data: &[u8]) {
+        self.0.update((data.len() as u64).to_be_bytes());
+        self.0.update(data);
+    }
+
+    fn finish(self) -> CacheKey {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&self.0.finalize());
+        CacheKey(key)
+    }
+}
+
+// scheme 和 host 统一小写、去掉默认端口和 fragment，统一 percent-encoding
+// 无法解析的 url 原样使用
+fn normalize_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.into()
+        }
+        Err(_) => url.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::{resize, Spec};
+
+    #[test]
+    fn source_key_should_be_stable() {
+        // 固定的值，保证 key 不会因为升级依赖或者 rust 版本而改变，否则磁盘缓存会全部失效
+        let key = CacheKey::source("https://example.com/a.jpg");
+        assert_eq!(
+            key.to_hex(),
+            "cdab8c6dae3de320b1c80e2c2ec01a0ee9180a1c1b2b5a4975b0428725e29f79"
+        );
+        assert_eq!(CacheKey::from_hex(&key.to_hex()), Some(key));
+        assert_eq!(CacheKey::from_hex("not a key"), None);
+    }
+
+    #[test]
+    fn equivalent_urls_should_have_same_key() {
+        assert_eq!(
+            CacheKey::source("HTTPS://Example.COM:443/a.jpg#top"),
+            CacheKey::source("https://example.com/a.jpg")
+        );
+        assert_ne!(
+            CacheKey::source("https://example.com/a.jpg"),
+            CacheKey::source("https://example.com/b.jpg")
+        );
+    }
+
+    #[test]
+    fn thumbnail_key_should_depend_on_spec_and_format() {
+        let url = "https://example.com/a.jpg";
+        let spec1 = ImageSpec::new(vec![Spec::new_resize(
+            100,
+            100,
+            resize::SampleFilter::Nearest,
+        )]);
+        let spec2 = ImageSpec::new(vec![Spec::new_resize(
+            200,
+            100,
+            resize::SampleFilter::Nearest,
+        )]);
+        let jpeg = OutputFormat::Jpeg(85);
+
+        let key = CacheKey::thumbnail(url, &spec1, jpeg);
+        assert_eq!(key, CacheKey::thumbnail(url, &spec1, jpeg));
+        assert_ne!(key, CacheKey::thumbnail(url, &spec2, jpeg));
+        assert_ne!(
+            key,
+            CacheKey::thumbnail(url, &spec1, OutputFormat::Jpeg(60))
+        );
+        assert_ne!(key, CacheKey::thumbnail(url, &spec1, OutputFormat::Png));
+        assert_ne!(key, CacheKey::source(url));
+    }
+}
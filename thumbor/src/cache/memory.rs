@@ -0,0 +1,151 @@
+use super::CacheKey;
+use bytes::Bytes;
+use lru::LruCache;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+// 缓存的统计数据，用于监控
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // 因为空间不足被淘汰的条目数
+    pub evictions: u64,
+    // 因为过期被删除的条目数
+    pub expirations: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct Entry {
+    data: Bytes,
+    expires_at: Instant,
+}
+
+// 按照总字节数限制大小的内存 LRU 缓存，每个条目有自己的过期时间
+pub struct MemoryCache {
+    entries: LruCache<CacheKey, Entry>,
+    max_bytes: usize,
+    stats: CacheStats,
+}
+
+impl MemoryCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            max_bytes,
+            stats: CacheStats::default(),
+        }
+    }
+
+    // 返回缓存的数据以及剩余的有效时间
+    pub fn get(&mut self, key: &CacheKey) -> Option<(Bytes, Duration)> {
+        let now = Instant::now();
+        let expired = match self.entries.get(key) {
+            Some(entry) if entry.expires_at > now => {
+                self.stats.hits += 1;
+                return Some((entry.data.clone(), entry.expires_at - now));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            self.remove(key);
+            self.stats.expirations += 1;
+        }
+        self.stats.misses += 1;
+        None
+    }
+
+    pub fn put(&mut self, key: CacheKey, data: Bytes, ttl: Duration) {
+        // 单个条目超过总大小时直接不缓存，免得把其他条目都挤掉
+        if data.len() > self.max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.stats.bytes + data.len() > self.max_bytes {
+            match self.entries.pop_lru() {
+                Some((_, entry)) => {
+                    self.stats.bytes -= entry.data.len();
+                    self.stats.evictions += 1;
+                }
+                None => break,
+            }
+        }
+        self.stats.bytes += data.len();
+        let expires_at = Instant::now() + ttl;
+        self.entries.put(key, Entry { data, expires_at });
+        self.stats.entries = self.entries.len();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.pop(key) {
+            self.stats.bytes -= entry.data.len();
+        }
+        self.stats.entries = self.entries.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn key(n: u32) -> CacheKey {
+        CacheKey::source(&format!("https://example.com/{}.jpg", n))
+    }
+
+    #[test]
+    fn cache_should_evict_by_total_bytes() {
+        let mut cache = MemoryCache::new(10);
+        cache.put(key(1), Bytes::from_static(b"12345"), TTL);
+        cache.put(key(2), Bytes::from_static(b"12345"), TTL);
+        // 访问 1，让 2 变成最久未使用的条目
+        assert!(cache.get(&key(1)).is_some());
+        cache.put(key(3), Bytes::from_static(b"123"), TTL);
+
+        assert!(cache.get(&key(2)).is_none());
+        assert!(cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(3)).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.bytes, 8);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn get_should_return_remaining_ttl() {
+        let mut cache = MemoryCache::new(10);
+        cache.put(key(1), Bytes::from_static(b"12345"), TTL);
+        let (data, ttl) = cache.get(&key(1)).unwrap();
+        assert_eq!(data, Bytes::from_static(b"12345"));
+        assert!(ttl <= TTL && ttl > TTL - Duration::from_secs(5));
+    }
+
+    #[test]
+    fn oversized_entry_should_not_be_cached() {
+        let mut cache = MemoryCache::new(4);
+        cache.put(key(1), Bytes::from_static(b"12345"), TTL);
+        assert!(cache.get(&key(1)).is_none());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn expired_entry_should_be_removed() {
+        let mut cache = MemoryCache::new(10);
+        cache.put(key(1), Bytes::from_static(b"12345"), Duration::ZERO);
+        assert!(cache.get(&key(1)).is_none());
+        let stats = cache.stats();
+        assert_eq!(stats.expirations, 1);
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.bytes, 0);
+    }
+}
@@ -0,0 +1,114 @@
+use reqwest::header::{HeaderMap, CACHE_CONTROL, EXPIRES};
+use std::time::{Duration, SystemTime};
+
+// 根据上游返回的 Cache-Control / Expires 计算缓存时间，返回 None 表示不应该缓存
+// 上游没有给出缓存策略时使用 default_ttl，且不会超过 max_ttl
+pub fn ttl_from_headers(
+    headers: &HeaderMap,
+    default_ttl: Duration,
+    max_ttl: Duration,
+) -> Option<Duration> {
+    let ttl = match cache_control_ttl(headers) {
+        Some(ttl) => ttl?,
+        None => expires_ttl(headers).unwrap_or(default_ttl),
+    };
+    if ttl.is_zero() {
+        return None;
+    }
+    Some(ttl.min(max_ttl))
+}
+
+// 外层 None 表示 Cache-Control 里没有相关的指令，内层 None 表示不允许缓存
+fn cache_control_ttl(headers: &HeaderMap) -> Option<Option<Duration>> {
+    let value = headers.get(CACHE_CONTROL)?.to_str().ok()?;
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for directive in value.split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            Some(("max-age", v)) => max_age = v.trim_matches('"').parse::<u64>().ok(),
+            Some(("s-maxage", v)) => s_maxage = v.trim_matches('"').parse::<u64>().ok(),
+            // 我们是共享缓存，private 的内容也不能缓存
+            None if matches!(directive.as_str(), "no-store" | "no-cache" | "private") => {
+                return Some(None)
+            }
+            _ => {}
+        }
+    }
+    // s-maxage 专门针对共享缓存，优先级高于 max-age
+    s_maxage
+        .or(max_age)
+        .map(|secs| Some(Duration::from_secs(secs)))
+}
+
+fn expires_ttl(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(EXPIRES)?.to_str().ok()?;
+    // 无法解析的 Expires 按照 RFC 7234 视为已经过期
+    let expires = match httpdate::parse_http_date(value) {
+        Ok(v) => v,
+        Err(_) => return Some(Duration::ZERO),
+    };
+    Some(
+        expires
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const DEFAULT: Duration = Duration::from_secs(60);
+    const MAX: Duration = Duration::from_secs(3600);
+
+    fn headers(name: reqwest::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn missing_headers_should_use_default_ttl() {
+        assert_eq!(
+            ttl_from_headers(&HeaderMap::new(), DEFAULT, MAX),
+            Some(DEFAULT)
+        );
+    }
+
+    #[test]
+    fn cache_control_should_be_honoured() {
+        let h = headers(CACHE_CONTROL, "public, max-age=120");
+        assert_eq!(
+            ttl_from_headers(&h, DEFAULT, MAX),
+            Some(Duration::from_secs(120))
+        );
+
+        let h = headers(CACHE_CONTROL, "max-age=120, s-maxage=300");
+        assert_eq!(
+            ttl_from_headers(&h, DEFAULT, MAX),
+            Some(Duration::from_secs(300))
+        );
+
+        let h = headers(CACHE_CONTROL, "max-age=999999");
+        assert_eq!(ttl_from_headers(&h, DEFAULT, MAX), Some(MAX));
+
+        let h = headers(CACHE_CONTROL, "no-store");
+        assert_eq!(ttl_from_headers(&h, DEFAULT, MAX), None);
+
+        let h = headers(CACHE_CONTROL, "max-age=0");
+        assert_eq!(ttl_from_headers(&h, DEFAULT, MAX), None);
+    }
+
+    #[test]
+    fn expires_should_be_honoured() {
+        let h = headers(EXPIRES, "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(ttl_from_headers(&h, DEFAULT, MAX), None);
+
+        let future = SystemTime::now() + Duration::from_secs(600);
+        let h = headers(EXPIRES, &httpdate::fmt_http_date(future));
+        let ttl = ttl_from_headers(&h, DEFAULT, MAX).unwrap();
+        assert!(ttl <= Duration::from_secs(600) && ttl > Duration::from_secs(590));
+    }
+}
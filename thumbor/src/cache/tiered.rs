@@ -0,0 +1,53 @@
+use super::{CacheKey, CacheStats, DiskCache, MemoryCache};
+use bytes::Bytes;
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+
+// 一个缓存层级各部分的统计数据
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TierStats {
+    pub memory: CacheStats,
+    pub disk: Option<CacheStats>,
+}
+
+// 内存缓存在前，可选的磁盘缓存在后
+pub struct TieredCache {
+    // 内存缓存的操作都很快，只在每次操作时短暂地加锁，不会跨 await 持有
+    memory: Mutex<MemoryCache>,
+    disk: Option<DiskCache>,
+}
+
+impl TieredCache {
+    pub fn new(memory: MemoryCache, disk: Option<DiskCache>) -> Self {
+        Self {
+            memory: Mutex::new(memory),
+            disk,
+        }
+    }
+
+    // 返回缓存的数据以及剩余的有效时间
+    pub async fn get(&self, key: &CacheKey) -> Option<(Bytes, Duration)> {
+        let hit = self.memory.lock().unwrap().get(key);
+        if hit.is_some() {
+            return hit;
+        }
+        let (data, ttl) = self.disk.as_ref()?.get(key).await?;
+        // 从磁盘读到的条目放回内存，下次就不用再读磁盘了
+        self.memory.lock().unwrap().put(*key, data.clone(), ttl);
+        Some((data, ttl))
+    }
+
+    pub async fn put(&self, key: CacheKey, data: Bytes, ttl: Duration) {
+        if let Some(disk) = self.disk.as_ref() {
+            disk.put(key, &data, ttl).await;
+        }
+        self.memory.lock().unwrap().put(key, data, ttl);
+    }
+
+    pub fn stats(&self) -> TierStats {
+        TierStats {
+            memory: self.memory.lock().unwrap().stats(),
+            disk: self.disk.as_ref().map(|disk| disk.stats()),
+        }
+    }
+}
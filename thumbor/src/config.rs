@@ -0,0 +1,610 @@
+use crate::{
+    engine::DecodeLimits,
+    format::DEFAULT_JPEG_QUALITY,
+    pb::ImageSpec,
+    source::{FetchLimits, S3Config, SourceGuard},
+};
+use clap::Parser;
+use reqwest::Url;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("failed to parse {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("invalid `{field}`: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+/// 基于 protobuf spec 的图片缩略图服务
+///
+/// 配置的优先级：命令行参数 > 环境变量 > 配置文件 > 默认值
+#[derive(Parser, Debug, Default)]
+#[clap(version)]
+pub struct Args {
+    /// TOML 格式的配置文件
+    #[clap(short, long, env = "THUMBOR_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// 监听的地址
+    #[clap(long, env = "THUMBOR_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// 同时处理的最大请求数
+    #[clap(long, env = "THUMBOR_CONCURRENCY_LIMIT")]
+    pub concurrency_limit: Option<usize>,
+
+    /// 单个请求的超时时间（秒）
+    #[clap(long, env = "THUMBOR_REQUEST_TIMEOUT")]
+    pub request_timeout: Option<u64>,
+
+    /// 下载原图的超时时间（秒）
+    #[clap(long, env = "THUMBOR_FETCH_TIMEOUT")]
+    pub fetch_timeout: Option<u64>,
+
+    /// 原图内存缓存的大小（字节）
+    #[clap(long, env = "THUMBOR_SOURCE_CACHE_BYTES")]
+    pub source_cache_bytes: Option<usize>,
+
+    /// 缩略图内存缓存的大小（字节）
+    #[clap(long, env = "THUMBOR_THUMBNAIL_CACHE_BYTES")]
+    pub thumbnail_cache_bytes: Option<usize>,
+
+    /// 磁盘缓存的目录，原图和缩略图分别存在它的 source 和 thumbnail 子目录下
+    #[clap(long, env = "THUMBOR_DISK_CACHE_DIR")]
+    pub disk_cache_dir: Option<PathBuf>,
+
+    /// 允许的原图来源，逗号分隔；可以是 host（支持 `*.example.com`）或者 url 前缀
+    #[clap(long, env = "THUMBOR_ALLOWED_SOURCES", use_value_delimiter = true)]
+    pub allowed_sources: Option<Vec<String>>,
+
+    /// url 签名的 key，设置之后只接受带签名的请求
+    #[clap(long, env = "THUMBOR_SIGNING_KEY", hide_env_values = true)]
+    pub signing_key: Option<String>,
+
+    /// 没有指定时 JPEG 使用的压缩质量（1-100）
+    #[clap(long, env = "THUMBOR_DEFAULT_QUALITY")]
+    pub default_quality: Option<u8>,
+
+    /// 是否去掉输出图片中的元数据（EXIF、GPS 位置等），默认去掉
+    #[clap(long, env = "THUMBOR_STRIP_METADATA")]
+    pub strip_metadata: Option<bool>,
+
+    /// 允许通过 `file:///path` 读取这个目录下的原图
+    #[clap(long, env = "THUMBOR_FILE_ROOT")]
+    pub file_root: Option<PathBuf>,
+
+    /// 对象存储的 endpoint，设置之后允许通过 `s3://bucket/key` 读取原图
+    #[clap(long, env = "THUMBOR_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    /// 对象存储的 region
+    #[clap(long, env = "THUMBOR_S3_REGION")]
+    pub s3_region: Option<String>,
+
+    /// 对象存储的 access key
+    #[clap(long, env = "THUMBOR_S3_ACCESS_KEY")]
+    pub s3_access_key: Option<String>,
+
+    /// 对象存储的 secret key
+    #[clap(long, env = "THUMBOR_S3_SECRET_KEY", hide_env_values = true)]
+    pub s3_secret_key: Option<String>,
+
+    /// 使用 `endpoint/bucket/key` 的形式访问对象存储，MinIO 需要打开
+    #[clap(long, env = "THUMBOR_S3_PATH_STYLE")]
+    pub s3_path_style: Option<bool>,
+
+    /// 允许读取的 bucket，逗号分隔
+    #[clap(long, env = "THUMBOR_S3_BUCKETS", use_value_delimiter = true)]
+    pub s3_buckets: Option<Vec<String>>,
+}
+
+// 服务的全部配置，配置文件里没有写的字段使用默认值
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub source: SourceConfig,
+    pub image: ImageConfig,
+    // 可以在 spec 里按名字引用的水印图片，启动时加载
+    pub watermarks: HashMap<String, PathBuf>,
+    // 常用的 spec 组合，可以在 url 里用 `preset:名字` 引用
+    pub presets: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    pub concurrency_limit: usize,
+    // 秒
+    pub request_timeout: u64,
+    pub signing_key: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: ([127, 0, 0, 1], 3000).into(),
+            concurrency_limit: 1024,
+            request_timeout: 10,
+            signing_key: None,
+        }
+    }
+}
+
+// 缓存时间的上限，太大的值会让 `now + ttl` 溢出
+const MAX_CACHE_TTL: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub source_memory_bytes: usize,
+    pub thumbnail_memory_bytes: usize,
+    // 设置了才启用磁盘缓存
+    pub disk_dir: Option<PathBuf>,
+    pub source_disk_bytes: u64,
+    pub thumbnail_disk_bytes: u64,
+    // 上游没有给出缓存策略时的缓存时间（秒），缩略图也使用这个时间
+    pub default_ttl: u64,
+    // 无论上游怎么说，最多缓存这么久（秒）
+    pub max_ttl: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            source_memory_bytes: 256 * 1024 * 1024,
+            thumbnail_memory_bytes: 128 * 1024 * 1024,
+            disk_dir: None,
+            source_disk_bytes: 4 * 1024 * 1024 * 1024,
+            thumbnail_disk_bytes: 2 * 1024 * 1024 * 1024,
+            default_ttl: 60 * 60,
+            max_ttl: 24 * 60 * 60,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn default_ttl(&self) -> Duration {
+        Duration::from_secs(self.default_ttl)
+    }
+
+    pub fn max_ttl(&self) -> Duration {
+        Duration::from_secs(self.max_ttl)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceConfig {
+    // 为空时允许任意公网地址
+    pub allowed: Vec<String>,
+    pub allow_private: bool,
+    pub max_redirects: usize,
+    pub max_bytes: u64,
+    // 秒
+    pub fetch_timeout: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub file_root: Option<PathBuf>,
+    pub s3: Option<S3Section>,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        let fetch = FetchLimits::default();
+        let decode = DecodeLimits::default();
+        Self {
+            allowed: Vec::new(),
+            allow_private: false,
+            max_redirects: SourceGuard::default().max_redirects,
+            max_bytes: fetch.max_bytes,
+            fetch_timeout: fetch.timeout.as_secs(),
+            max_width: decode.max_width,
+            max_height: decode.max_height,
+            max_pixels: decode.max_pixels,
+            file_root: None,
+            s3: None,
+        }
+    }
+}
+
+impl SourceConfig {
+    pub fn guard(&self) -> SourceGuard {
+        let guard = SourceGuard {
+            allow_private: self.allow_private,
+            max_redirects: self.max_redirects,
+            ..Default::default()
+        };
+        guard
+            .allow(self.allowed.iter().cloned())
+            .expect("validated allow list")
+    }
+
+    pub fn fetch_limits(&self) -> FetchLimits {
+        FetchLimits {
+            max_bytes: self.max_bytes,
+            timeout: Duration::from_secs(self.fetch_timeout),
+        }
+    }
+
+    pub fn decode_limits(&self) -> DecodeLimits {
+        DecodeLimits {
+            max_width: self.max_width,
+            max_height: self.max_height,
+            max_pixels: self.max_pixels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Section {
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    #[serde(default)]
+    pub access_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+    #[serde(default)]
+    pub path_style: bool,
+    // 允许读取的 bucket，不在列表里的 bucket 不会用我们的凭证去访问
+    #[serde(default)]
+    pub buckets: Vec<String>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
+
+impl S3Section {
+    // endpoint 在 validate 时已经检查过
+    pub fn to_s3_config(&self) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint.parse().expect("validated s3 endpoint"),
+            region: self.region.clone(),
+            access_key: self.access_key.clone(),
+            secret_key: self.secret_key.clone(),
+            path_style: self.path_style,
+            buckets: self.buckets.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    pub default_quality: u8,
+    // 为 false 时 JPEG 输出会保留原图的 EXIF（orientation 已经处理过）
+    pub strip_metadata: bool,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            default_quality: DEFAULT_JPEG_QUALITY,
+            strip_metadata: true,
+        }
+    }
+}
+
+impl Config {
+    // 读取配置文件，再用命令行参数和环境变量覆盖，最后检查配置是否合法
+    pub fn load(args: Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.merge(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    fn merge(&mut self, args: Args) {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+
+        set(&mut self.server.listen, args.listen);
+        set(&mut self.server.concurrency_limit, args.concurrency_limit);
+        set(&mut self.server.request_timeout, args.request_timeout);
+        if args.signing_key.is_some() {
+            self.server.signing_key = args.signing_key;
+        }
+        set(&mut self.cache.source_memory_bytes, args.source_cache_bytes);
+        set(
+            &mut self.cache.thumbnail_memory_bytes,
+            args.thumbnail_cache_bytes,
+        );
+        if args.disk_cache_dir.is_some() {
+            self.cache.disk_dir = args.disk_cache_dir;
+        }
+        set(&mut self.source.allowed, args.allowed_sources);
+        set(&mut self.source.fetch_timeout, args.fetch_timeout);
+        if args.file_root.is_some() {
+            self.source.file_root = args.file_root;
+        }
+        set(&mut self.image.default_quality, args.default_quality);
+        set(&mut self.image.strip_metadata, args.strip_metadata);
+
+        if let Some(endpoint) = args.s3_endpoint {
+            let s3 = self.source.s3.get_or_insert_with(|| S3Section {
+                endpoint: String::new(),
+                region: default_region(),
+                access_key: String::new(),
+                secret_key: String::new(),
+                path_style: false,
+                buckets: Vec::new(),
+            });
+            s3.endpoint = endpoint;
+        }
+        if let Some(s3) = self.source.s3.as_mut() {
+            set(&mut s3.region, args.s3_region);
+            set(&mut s3.access_key, args.s3_access_key);
+            set(&mut s3.secret_key, args.s3_secret_key);
+            set(&mut s3.path_style, args.s3_path_style);
+            set(&mut s3.buckets, args.s3_buckets);
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn check(ok: bool, field: &'static str, reason: &str) -> Result<(), ConfigError> {
+            if ok {
+                return Ok(());
+            }
+            Err(ConfigError::Invalid {
+                field,
+                reason: reason.to_owned(),
+            })
+        }
+
+        let server = &self.server;
+        check(
+            server.concurrency_limit > 0,
+            "server.concurrency_limit",
+            "must be positive",
+        )?;
+        check(
+            server.request_timeout > 0,
+            "server.request_timeout",
+            "must be positive",
+        )?;
+        if let Some(key) = &server.signing_key {
+            check(!key.is_empty(), "server.signing_key", "must not be empty")?;
+        }
+
+        let cache = &self.cache;
+        check(
+            cache.default_ttl > 0,
+            "cache.default_ttl",
+            "must be positive",
+        )?;
+        check(
+            cache.default_ttl <= MAX_CACHE_TTL,
+            "cache.default_ttl",
+            "must not exceed one year",
+        )?;
+        check(
+            cache.max_ttl >= cache.default_ttl,
+            "cache.max_ttl",
+            "must not be less than cache.default_ttl",
+        )?;
+        check(
+            cache.max_ttl <= MAX_CACHE_TTL,
+            "cache.max_ttl",
+            "must not exceed one year",
+        )?;
+
+        let source = &self.source;
+        check(source.max_bytes > 0, "source.max_bytes", "must be positive")?;
+        check(
+            source.fetch_timeout > 0,
+            "source.fetch_timeout",
+            "must be positive",
+        )?;
+        check(
+            source.max_width > 0 && source.max_height > 0 && source.max_pixels > 0,
+            "source.max_width/max_height/max_pixels",
+            "must be positive",
+        )?;
+        for entry in &source.allowed {
+            let valid = if entry.contains("://") {
+                matches!(Url::parse(entry), Ok(url) if url.has_host())
+            } else {
+                !entry.is_empty() && !entry.contains('/')
+            };
+            check(
+                valid,
+                "source.allowed",
+                &format!("`{}` is neither a host nor a url prefix", entry),
+            )?;
+        }
+        if let Some(root) = &source.file_root {
+            check(
+                root.is_dir(),
+                "source.file_root",
+                &format!("{:?} is not a directory", root),
+            )?;
+        }
+        if let Some(s3) = &source.s3 {
+            let valid = matches!(
+                Url::parse(&s3.endpoint),
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host()
+            );
+            check(
+                valid,
+                "source.s3.endpoint",
+                &format!("`{}` is not a http(s) url", s3.endpoint),
+            )?;
+            check(
+                !s3.buckets.is_empty() && s3.buckets.iter().all(|b| !b.is_empty()),
+                "source.s3.buckets",
+                "must list at least one bucket",
+            )?;
+        }
+
+        check(
+            (1..=100).contains(&self.image.default_quality),
+            "image.default_quality",
+            "must be between 1 and 100",
+        )?;
+
+        for (name, spec) in &self.presets {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            check(
+                valid,
+                "presets",
+                &format!("`{}` is not a valid preset name", name),
+            )?;
+            if let Err(e) = ImageSpec::try_from(spec.as_str()) {
+                return Err(ConfigError::Invalid {
+                    field: "presets",
+                    reason: format!("`{}` is not a valid spec: {}", name, e),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [server]
+        listen = "0.0.0.0:8080"
+        signing_key = "secret"
+
+        [cache]
+        disk_dir = "/var/cache/thumbor"
+        default_ttl = 600
+
+        [source]
+        allowed = ["images.example.com", "https://cdn.example.com/public/"]
+
+        [source.s3]
+        endpoint = "http://127.0.0.1:9000"
+        path_style = true
+        buckets = ["photos"]
+
+        [image]
+        default_quality = 70
+        strip_metadata = false
+
+        [watermarks]
+        logo = "/etc/thumbor/logo.png"
+
+        [presets]
+        avatar = "resize(128,128,fit=cover)|format(webp)"
+    "#;
+
+    fn args(argv: &[&str]) -> Args {
+        Args::parse_from(std::iter::once("thumbor").chain(argv.iter().copied()))
+    }
+
+    #[test]
+    fn config_file_should_be_parsed() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.server.listen, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.server.signing_key.as_deref(), Some("secret"));
+        // 没有写的字段使用默认值
+        assert_eq!(config.server.concurrency_limit, 1024);
+        assert_eq!(config.cache.default_ttl(), Duration::from_secs(600));
+        assert_eq!(config.source.allowed.len(), 2);
+        assert_eq!(config.source.s3.as_ref().unwrap().region, "us-east-1");
+        assert_eq!(config.image.default_quality, 70);
+        assert!(!config.image.strip_metadata);
+        assert_eq!(
+            config.watermarks["logo"],
+            PathBuf::from("/etc/thumbor/logo.png")
+        );
+        assert_eq!(
+            config.presets["avatar"],
+            "resize(128,128,fit=cover)|format(webp)"
+        );
+    }
+
+    #[test]
+    fn args_should_override_config_file() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.merge(args(&[
+            "--listen",
+            "127.0.0.1:4000",
+            "--allowed-sources",
+            "a.com,*.b.com",
+            "--default-quality",
+            "90",
+            "--strip-metadata",
+            "true",
+        ]));
+        config.validate().unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.source.allowed, vec!["a.com", "*.b.com"]);
+        assert_eq!(config.image.default_quality, 90);
+        assert!(config.image.strip_metadata);
+        assert_eq!(config.cache.default_ttl, 600);
+    }
+
+    #[test]
+    fn invalid_config_should_be_rejected() {
+        let cases = [
+            "[image]\ndefault_quality = 0",
+            "[server]\nconcurrency_limit = 0",
+            "[cache]\ndefault_ttl = 600\nmax_ttl = 60",
+            "[cache]\nmax_ttl = 31622400",
+            "[cache]\ndefault_ttl = 31622400\nmax_ttl = 31622400",
+            "[source]\nallowed = [\"example.com/a\"]",
+            "[source.s3]\nendpoint = \"ftp://example.com\"",
+            "[source.s3]\nendpoint = \"http://127.0.0.1:9000\"",
+            "[presets]\navatar = \"resize(128\"",
+            "[presets]\nnested = \"preset:avatar\"",
+            "[presets]\n\"a/b\" = \"fliph\"",
+        ];
+        for case in cases {
+            let config: Config = toml::from_str(case).unwrap();
+            assert!(
+                matches!(config.validate(), Err(ConfigError::Invalid { .. })),
+                "{} should be invalid",
+                case
+            );
+        }
+        assert!(toml::from_str::<Config>("[server]\nunknown = 1").is_err());
+    }
+}
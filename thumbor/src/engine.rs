@@ -1,19 +1,30 @@
-use crate::pb::Spec;
-use image::ImageOutputFormat;
+use crate::{format::OutputFormat, pb::Spec};
 
+mod error;
+mod info;
+mod limits;
+mod metadata;
 mod photon;
+mod rotate;
+mod smartcrop;
+mod watermark;
+pub use error::EngineError;
+pub use info::{ImageInfo, PaletteColor};
+pub use limits::DecodeLimits;
 pub use photon::Photon;
+pub use watermark::Watermarks;
 
 // Engine trait：未来可以添加更多的 engine，主流程只需要替换 engine
 pub trait Engine {
     // 对 engine 按照 specs 进行一系列有序的处理
-    fn apply(&mut self, specs: &[Spec]);
+    fn apply(&mut self, specs: &[Spec]) -> Result<(), EngineError>;
     // 从 engine 中生成目标图片，注意这里用的是 self，而非 self 的引用
-    fn generate(self, format: ImageOutputFormat) -> Vec<u8>;
+    // specs 里指定的输出格式优先于传入的 format，返回值里带上实际使用的格式
+    fn generate(self, format: OutputFormat) -> Result<(Vec<u8>, OutputFormat), EngineError>;
 }
 
 // SpecTransform：未来如果添加更多的 spec，只需要实现它即可
 pub trait SpecTransform<T> {
     // 对图片使用 op 做 transform
-    fn transform(&mut self, op: T);
+    fn transform(&mut self, op: T) -> Result<(), EngineError>;
 }
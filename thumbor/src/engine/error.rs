@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+// engine 处理图片时可能出现的错误，spec 来自 url，不能相信里面的任何数据
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("invalid value {value} for {field}")]
+    InvalidEnumValue { field: &'static str, value: i32 },
+
+    #[error("invalid {field}: {reason}")]
+    InvalidParameter {
+        field: &'static str,
+        reason: &'static str,
+    },
+
+    #[error("crop ({x1}, {y1}, {x2}, {y2}) is out of bounds for a {width}x{height} image")]
+    CropOutOfBounds {
+        x1: u32,
+        y1: u32,
+        x2: u32,
+        y2: u32,
+        width: u32,
+        height: u32,
+    },
+
+    #[error("cannot resize to {width}x{height}")]
+    ZeroSizeResize { width: u32, height: u32 },
+
+    #[error("failed to decode image: {0}")]
+    Decode(String),
+
+    #[error("image {width}x{height} exceeds the size limit")]
+    ImageTooLarge { width: u32, height: u32 },
+
+    #[error("failed to encode image: {0}")]
+    Encode(String),
+}
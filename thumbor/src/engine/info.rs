@@ -0,0 +1,163 @@
+use super::{metadata::Exif, DecodeLimits, EngineError};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// 计算调色板时先把图片缩小到这个尺寸以内
+const PALETTE_SAMPLE: u32 = 64;
+// 调色板里最多的颜色数
+const PALETTE_SIZE: usize = 5;
+
+// 原图的基本信息，客户端可以据此决定使用什么 spec
+#[derive(Debug, Serialize)]
+pub struct ImageInfo {
+    // 按 EXIF orientation 旋转之后的宽高，和处理 spec 时看到的一致
+    pub width: u32,
+    pub height: u32,
+    pub format: &'static str,
+    pub bytes: usize,
+    // 取值 1-8，没有 EXIF 时为 1
+    pub orientation: u32,
+    pub alpha: bool,
+    // 按占比从大到小排列，第一个就是主色
+    pub palette: Vec<PaletteColor>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PaletteColor {
+    // RGB 的十六进制，比如 #ff0000
+    pub color: String,
+    // 在不透明的像素中所占的比例
+    pub ratio: f32,
+}
+
+impl ImageInfo {
+    pub fn inspect(data: &[u8], limits: DecodeLimits) -> Result<Self, EngineError> {
+        // 解码之前先检查尺寸
+        let (width, height) = limits.check(data)?;
+        let format = image::guess_format(data).map_err(|e| EngineError::Decode(e.to_string()))?;
+        let image = image::load_from_memory_with_format(data, format)
+            .map_err(|e| EngineError::Decode(e.to_string()))?;
+
+        let orientation = Exif::read(data).map_or(1, |exif| exif.orientation);
+        // 5-8 需要旋转 90 度，宽高互换
+        let (width, height) = match orientation {
+            5..=8 => (height, width),
+            _ => (width, height),
+        };
+        Ok(Self {
+            width,
+            height,
+            format: format_name(format),
+            bytes: data.len(),
+            orientation,
+            alpha: image.color().has_alpha(),
+            palette: palette(&image),
+        })
+    }
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Gif => "gif",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::Tiff => "tiff",
+        format => format
+            .extensions_str()
+            .first()
+            .copied()
+            .unwrap_or("unknown"),
+    }
+}
+
+// 每个通道取高 4 位分桶，取像素最多的几个桶里的平均颜色，忽略（半）透明的像素
+fn palette(image: &DynamicImage) -> Vec<PaletteColor> {
+    let sample = if image.width().max(image.height()) > PALETTE_SAMPLE {
+        image.thumbnail(PALETTE_SAMPLE, PALETTE_SAMPLE).to_rgba8()
+    } else {
+        image.to_rgba8()
+    };
+    // 每个桶里 r、g、b 的和以及像素数
+    let mut buckets: HashMap<u16, [u64; 4]> = HashMap::new();
+    let mut total = 0;
+    for p in sample.pixels() {
+        let [r, g, b, a] = p.0;
+        if a < 128 {
+            continue;
+        }
+        let key = ((r as u16 >> 4) << 8) | ((g as u16 >> 4) << 4) | (b as u16 >> 4);
+        let bucket = buckets.entry(key).or_default();
+        bucket[0] += r as u64;
+        bucket[1] += g as u64;
+        bucket[2] += b as u64;
+        bucket[3] += 1;
+        total += 1;
+    }
+
+    let mut buckets: Vec<_> = buckets.into_iter().collect();
+    // 像素数相同时按颜色排序，保证结果稳定
+    buckets.sort_by(|(ka, a), (kb, b)| b[3].cmp(&a[3]).then(ka.cmp(kb)));
+    buckets
+        .into_iter()
+        .take(PALETTE_SIZE)
+        .map(|(_, [r, g, b, n])| PaletteColor {
+            color: format!("#{:02x}{:02x}{:02x}", r / n, g / n, b / n),
+            ratio: n as f32 / total as f32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::metadata::tests::{exif_with_orientation, jpeg};
+    use super::*;
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+    #[test]
+    fn info_should_describe_image() {
+        // 左边 3/4 是红色，右边 1/4 是透明的蓝色，透明的部分不计入调色板
+        let image = RgbaImage::from_fn(40, 20, |x, _| {
+            if x < 30 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 0])
+            }
+        });
+        let mut data = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .unwrap();
+
+        let info = ImageInfo::inspect(&data, DecodeLimits::default()).unwrap();
+        assert_eq!((info.width, info.height), (40, 20));
+        assert_eq!(info.format, "png");
+        assert_eq!(info.bytes, data.len());
+        assert_eq!(info.orientation, 1);
+        assert!(info.alpha);
+        assert_eq!(
+            info.palette,
+            vec![PaletteColor {
+                color: "#ff0000".to_owned(),
+                ratio: 1.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn info_should_apply_exif_orientation() {
+        let data = exif_with_orientation(6).embed_in_jpeg(jpeg(40, 20));
+        let info = ImageInfo::inspect(&data, DecodeLimits::default()).unwrap();
+        assert_eq!((info.width, info.height), (20, 40));
+        assert_eq!(info.format, "jpeg");
+        assert_eq!(info.orientation, 6);
+        assert!(!info.alpha);
+    }
+
+    #[test]
+    fn invalid_image_should_be_rejected() {
+        assert!(ImageInfo::inspect(b"not an image", DecodeLimits::default()).is_err());
+    }
+}
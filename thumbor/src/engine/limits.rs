@@ -0,0 +1,89 @@
+use super::EngineError;
+use image::io::Reader;
+use std::io::Cursor;
+
+// 解码前对图片尺寸的限制，防止很小的文件解码之后占用大量内存（decompression bomb）
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 10_000,
+            max_height: 10_000,
+            max_pixels: 50_000_000,
+        }
+    }
+}
+
+impl DecodeLimits {
+    // 只读取图片头里的宽高，不做完整的解码
+    pub fn check(&self, data: &[u8]) -> Result<(u32, u32), EngineError> {
+        let (width, height) = Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| EngineError::Decode(e.to_string()))?
+            .into_dimensions()
+            .map_err(|e| EngineError::Decode(e.to_string()))?;
+        self.check_size(width, height)?;
+        Ok((width, height))
+    }
+
+    // 处理过程中生成的图片（比如 resize 的目标尺寸）也不能超过限制
+    pub fn check_size(&self, width: u32, height: u32) -> Result<(), EngineError> {
+        if width > self.max_width
+            || height > self.max_height
+            || width as u64 * height as u64 > self.max_pixels
+        {
+            return Err(EngineError::ImageTooLarge { width, height });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        DynamicImage::new_rgba8(width, height)
+            .write_to(&mut buf, ImageOutputFormat::Png)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn image_within_limits_should_pass() {
+        let limits = DecodeLimits::default();
+        assert_eq!(limits.check(&png(20, 10)).unwrap(), (20, 10));
+    }
+
+    #[test]
+    fn oversized_image_should_be_rejected() {
+        let limits = DecodeLimits {
+            max_width: 100,
+            max_height: 100,
+            max_pixels: 1000,
+        };
+        for (w, h) in [(101, 1), (1, 101), (50, 50)] {
+            assert!(matches!(
+                limits.check(&png(w, h)),
+                Err(EngineError::ImageTooLarge { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_data_should_fail_to_decode() {
+        let limits = DecodeLimits::default();
+        assert!(matches!(
+            limits.check(b"not an image"),
+            Err(EngineError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+use exif::{In, Reader, Tag};
+use std::io::Cursor;
+
+// EXIF 里 Orientation 的 tag
+const ORIENTATION: u16 = 0x0112;
+// JPEG 的 APP1 段以这个标识开头，后面跟着 TIFF 格式的 EXIF 数据
+const EXIF_ID: &[u8] = b"Exif\0\0";
+
+// 原图中的 EXIF 信息
+pub(super) struct Exif {
+    // 图片需要怎样旋转/翻转才能正常显示，取值 1-8，1 表示不需要处理
+    pub orientation: u32,
+    // TIFF 格式的原始数据，保留元数据时原样写回输出的图片
+    pub raw: Vec<u8>,
+}
+
+impl Exif {
+    // 从 JPEG/PNG/WebP/TIFF 等格式的图片中读取 EXIF，没有或者格式有误时返回 None
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let exif = Reader::new()
+            .read_from_container(&mut Cursor::new(data))
+            .ok()?;
+        let orientation = exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .unwrap_or(1);
+        Some(Self {
+            orientation,
+            raw: exif.buf().to_vec(),
+        })
+    }
+
+    // 解码时已经按照 orientation 处理过图片了，写回时需要改成 1，否则会被旋转两次
+    pub fn reset_orientation(&mut self) {
+        let raw = &mut self.raw;
+        let little_endian = match raw.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return,
+        };
+        let read16 = |buf: &[u8], pos: usize| -> Option<u16> {
+            let bytes = [*buf.get(pos)?, *buf.get(pos + 1)?];
+            Some(if little_endian {
+                u16::from_le_bytes(bytes)
+            } else {
+                u16::from_be_bytes(bytes)
+            })
+        };
+        let read32 = |buf: &[u8], pos: usize| -> Option<u32> {
+            let bytes = buf.get(pos..pos + 4)?.try_into().ok()?;
+            Some(if little_endian {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            })
+        };
+
+        // Orientation 只会出现在第一个 IFD 里，类型是 SHORT，值直接存在 entry 中
+        let ifd = match read32(raw, 4) {
+            Some(offset) => offset as usize,
+            None => return,
+        };
+        let count = read16(raw, ifd).unwrap_or(0) as usize;
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            if read16(raw, entry) == Some(ORIENTATION) && read16(raw, entry + 2) == Some(3) {
+                let value = if little_endian {
+                    1u16.to_le_bytes()
+                } else {
+                    1u16.to_be_bytes()
+                };
+                if let Some(slot) = raw.get_mut(entry + 8..entry + 10) {
+                    slot.copy_from_slice(&value);
+                }
+                return;
+            }
+        }
+    }
+
+    // 把 EXIF 作为 APP1 段插入到 JPEG 的 SOI 之后，数据太大放不进一个段时不写入
+    pub fn embed_in_jpeg(&self, jpeg: Vec<u8>) -> Vec<u8> {
+        let len = 2 + EXIF_ID.len() + self.raw.len();
+        if !jpeg.starts_with(&[0xff, 0xd8]) || len > u16::MAX as usize {
+            return jpeg;
+        }
+        let mut buf = Vec::with_capacity(jpeg.len() + len + 2);
+        buf.extend_from_slice(&jpeg[..2]);
+        buf.extend_from_slice(&[0xff, 0xe1]);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+        buf.extend_from_slice(EXIF_ID);
+        buf.extend_from_slice(&self.raw);
+        buf.extend_from_slice(&jpeg[2..]);
+        buf
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+
+    // 只包含 Orientation 一个字段的 TIFF 数据
+    pub fn exif_with_orientation(orientation: u16) -> Exif {
+        let mut raw = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        raw.extend_from_slice(&ORIENTATION.to_le_bytes());
+        raw.extend_from_slice(&[3, 0, 1, 0, 0, 0]);
+        raw.extend_from_slice(&orientation.to_le_bytes());
+        raw.extend_from_slice(&[0; 6]);
+        Exif {
+            orientation: orientation as u32,
+            raw,
+        }
+    }
+
+    pub fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+        let mut buf = Vec::new();
+        image
+            .write_to(&mut buf, ImageOutputFormat::Jpeg(80))
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn embedded_exif_should_be_read_back() {
+        let data = exif_with_orientation(6).embed_in_jpeg(jpeg(4, 2));
+        let exif = Exif::read(&data).unwrap();
+        assert_eq!(exif.orientation, 6);
+        assert!(image::load_from_memory(&data).is_ok());
+    }
+
+    #[test]
+    fn reset_orientation_should_set_it_to_one() {
+        let mut exif = exif_with_orientation(8);
+        exif.reset_orientation();
+        let data = exif.embed_in_jpeg(jpeg(4, 2));
+        assert_eq!(Exif::read(&data).unwrap().orientation, 1);
+    }
+
+    #[test]
+    fn image_without_exif_should_return_none() {
+        assert!(Exif::read(&jpeg(4, 2)).is_none());
+        assert!(Exif::read(b"not an image").is_none());
+    }
+}
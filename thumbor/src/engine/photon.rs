@@ -1,15 +1,29 @@
-use super::{Engine, SpecTransform};
-use crate::pb::*;
+use super::{
+    metadata::Exif,
+    rotate, smartcrop,
+    watermark::{render_text, stamp, Watermarks},
+    DecodeLimits, Engine, EngineError, SpecTransform,
+};
+use crate::{
+    format::{OutputFormat, DEFAULT_JPEG_QUALITY},
+    pb::*,
+};
 use anyhow::Result;
 use bytes::Bytes;
 use image::{DynamicImage, ImageBuffer, ImageOutputFormat};
+use image_webp::{ColorType, WebPEncoder};
 use lazy_static::lazy_static;
 use photon_rs::{
-    effects, filters, multiple, native::open_image_from_bytes, transform, PhotonImage,
+    colour_spaces, conv, effects, filters, monochrome, native::open_image_from_bytes, transform,
+    PhotonImage,
 };
+use std::sync::Arc;
+
+// 高斯模糊允许的最大半径
+const MAX_BLUR_RADIUS: i32 = 100;
 
 lazy_static! {
-    // 预先把水印文件加载为静态变量
+    // 预先把水印文件加载为静态变量，spec 里没有指定水印来源时使用
     static ref WATERMARK: PhotonImage = {
         let data = include_bytes!("../../rust-logo.png");
         let watermark = open_image_from_bytes(data).unwrap();
@@ -18,105 +32,849 @@ lazy_static! {
 }
 
 // 我们目前支持 Photon engine
-pub struct Photon(PhotonImage);
+pub struct Photon {
+    image: PhotonImage,
+    // specs 里指定的输出格式
+    format: Option<OutputFormat>,
+    // specs 里指定 JPEG 但没有给出质量时使用
+    default_quality: u8,
+    // 原图中的 EXIF，为 None 时输出的图片不带任何元数据
+    exif: Option<Exif>,
+    // 配置里注册的水印
+    watermarks: Arc<Watermarks>,
+    // spec 里通过 url 指定的水印，处理之前已经下载好
+    url_watermarks: Watermarks,
+    // resize 等操作生成的图片尺寸限制
+    limits: DecodeLimits,
+}
 
 // 从 Bytes 转换成 Photon 结构
 impl TryFrom<Bytes> for Photon {
     type Error = anyhow::Error;
 
     fn try_from(data: Bytes) -> Result<Self, Self::Error> {
-        Ok(Self(open_image_from_bytes(&data)?))
+        let mut image = open_image_from_bytes(&data)?;
+        // 手机拍的照片通常是按传感器方向存储的，需要先按 EXIF 的 orientation 摆正
+        let mut exif = Exif::read(&data);
+        if let Some(exif) = exif.as_mut() {
+            image = orient(image, exif.orientation);
+            exif.reset_orientation();
+        }
+        Ok(Self {
+            image,
+            format: None,
+            default_quality: DEFAULT_JPEG_QUALITY,
+            exif,
+            watermarks: Arc::default(),
+            url_watermarks: Watermarks::default(),
+            limits: DecodeLimits::default(),
+        })
+    }
+}
+
+impl Photon {
+    pub fn with_default_quality(mut self, quality: u8) -> Self {
+        self.default_quality = quality;
+        self
+    }
+
+    pub fn with_watermarks(mut self, named: Arc<Watermarks>, by_url: Watermarks) -> Self {
+        self.watermarks = named;
+        self.url_watermarks = by_url;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // 截取的尺寸不能为 0，也不能超过图片本身
+    fn check_crop_size(&self, width: u32, height: u32) -> Result<(), EngineError> {
+        let (image_width, image_height) = (self.image.get_width(), self.image.get_height());
+        if width == 0 || height == 0 || width > image_width || height > image_height {
+            return Err(EngineError::CropOutOfBounds {
+                x1: 0,
+                y1: 0,
+                x2: width,
+                y2: height,
+                width: image_width,
+                height: image_height,
+            });
+        }
+        Ok(())
+    }
+
+    // 默认去掉所有元数据（比如 GPS 位置），keep 为 true 时保留原图的 EXIF
+    pub fn with_metadata(mut self, keep: bool) -> Self {
+        if !keep {
+            self.exif = None;
+        }
+        self
     }
 }
 
 impl Engine for Photon {
-    fn apply(&mut self, specs: &[Spec]) {
+    fn apply(&mut self, specs: &[Spec]) -> Result<(), EngineError> {
         for spec in specs.iter() {
             match spec.data {
-                Some(spec::Data::Crop(ref v)) => self.transform(v),
-                Some(spec::Data::Contrast(ref v)) => self.transform(v),
-                Some(spec::Data::Filter(ref v)) => self.transform(v),
-                Some(spec::Data::Fliph(ref v)) => self.transform(v),
-                Some(spec::Data::Flipv(ref v)) => self.transform(v),
-                Some(spec::Data::Resize(ref v)) => self.transform(v),
-                Some(spec::Data::Watermark(ref v)) => self.transform(v),
+                Some(spec::Data::Crop(ref v)) => self.transform(v)?,
+                Some(spec::Data::Contrast(ref v)) => self.transform(v)?,
+                Some(spec::Data::Filter(ref v)) => self.transform(v)?,
+                Some(spec::Data::Fliph(ref v)) => self.transform(v)?,
+                Some(spec::Data::Flipv(ref v)) => self.transform(v)?,
+                Some(spec::Data::Resize(ref v)) => self.transform(v)?,
+                Some(spec::Data::Watermark(ref v)) => self.transform(v)?,
+                Some(spec::Data::Format(ref v)) => self.transform(v)?,
+                Some(spec::Data::Brightness(ref v)) => self.transform(v)?,
+                Some(spec::Data::Saturation(ref v)) => self.transform(v)?,
+                Some(spec::Data::HueRotate(ref v)) => self.transform(v)?,
+                Some(spec::Data::Blur(ref v)) => self.transform(v)?,
+                Some(spec::Data::Sharpen(ref v)) => self.transform(v)?,
+                Some(spec::Data::Grayscale(ref v)) => self.transform(v)?,
+                Some(spec::Data::Rotate(ref v)) => self.transform(v)?,
+                Some(spec::Data::SmartCrop(ref v)) => self.transform(v)?,
+                Some(spec::Data::FocalCrop(ref v)) => self.transform(v)?,
                 // 对于目前不认识的 spec，不做任何处理
                 _ => {}
             }
         }
+        Ok(())
+    }
+
+    fn generate(self, format: OutputFormat) -> Result<(Vec<u8>, OutputFormat), EngineError> {
+        let format = self.format.unwrap_or(format);
+        let mut buf = image_to_buf(self.image, format)?;
+        // 目前只有 JPEG 可以写回 EXIF，其他格式输出时总是不带元数据
+        if let (Some(exif), OutputFormat::Jpeg(_)) = (&self.exif, format) {
+            buf = exif.embed_in_jpeg(buf);
+        }
+        Ok((buf, format))
     }
+}
 
-    fn generate(self, format: ImageOutputFormat) -> Vec<u8> {
-        image_to_buf(self.0, format)
+// 按照 EXIF orientation（1-8）旋转/翻转图片，先顺时针旋转再水平翻转
+fn orient(image: PhotonImage, orientation: u32) -> PhotonImage {
+    let (quarters, mirror) = match orientation {
+        2 => (0, true),
+        3 => (2, false),
+        4 => (2, true),
+        5 => (1, true),
+        6 => (1, false),
+        7 => (3, true),
+        8 => (3, false),
+        _ => return image,
+    };
+    let pixels = image.get_raw_pixels();
+    let (width, height) = (image.get_width(), image.get_height());
+    let (pixels, width, height) = rotate::rotate_quarters(&pixels, width, height, quarters);
+    let mut image = PhotonImage::new(pixels, width, height);
+    if mirror {
+        transform::fliph(&mut image);
     }
+    image
 }
 
 impl SpecTransform<&Crop> for Photon {
-    fn transform(&mut self, op: &Crop) {
-        let img = transform::crop(&mut self.0, op.x1, op.y1, op.x2, op.y2);
-        self.0 = img;
+    fn transform(&mut self, op: &Crop) -> Result<(), EngineError> {
+        let width = self.image.get_width();
+        let height = self.image.get_height();
+        // photon 不检查坐标，越界会直接 panic
+        if op.x1 >= op.x2 || op.y1 >= op.y2 || op.x2 > width || op.y2 > height {
+            return Err(EngineError::CropOutOfBounds {
+                x1: op.x1,
+                y1: op.y1,
+                x2: op.x2,
+                y2: op.y2,
+                width,
+                height,
+            });
+        }
+        let img = transform::crop(&mut self.image, op.x1, op.y1, op.x2, op.y2);
+        self.image = img;
+        Ok(())
+    }
+}
+
+impl SpecTransform<&SmartCrop> for Photon {
+    fn transform(&mut self, op: &SmartCrop) -> Result<(), EngineError> {
+        self.check_crop_size(op.width, op.height)?;
+        let pixels = self.image.get_raw_pixels();
+        let (width, height) = (self.image.get_width(), self.image.get_height());
+        let (x, y) = smartcrop::salient_region(&pixels, width, height, op.width, op.height);
+        self.image = transform::crop(&mut self.image, x, y, x + op.width, y + op.height);
+        Ok(())
+    }
+}
+
+impl SpecTransform<&FocalCrop> for Photon {
+    fn transform(&mut self, op: &FocalCrop) -> Result<(), EngineError> {
+        self.check_crop_size(op.width, op.height)?;
+        if !(0.0..=1.0).contains(&op.x) || !(0.0..=1.0).contains(&op.y) {
+            return Err(EngineError::InvalidParameter {
+                field: "focal_crop",
+                reason: "focal point must be between 0.0 and 1.0",
+            });
+        }
+        let (width, height) = (self.image.get_width(), self.image.get_height());
+        let (x, y) = smartcrop::focal_region(width, height, op.width, op.height, op.x, op.y);
+        self.image = transform::crop(&mut self.image, x, y, x + op.width, y + op.height);
+        Ok(())
     }
 }
 
 impl SpecTransform<&Contrast> for Photon {
-    fn transform(&mut self, op: &Contrast) {
-        effects::adjust_contrast(&mut self.0, op.contrast);
+    fn transform(&mut self, op: &Contrast) -> Result<(), EngineError> {
+        effects::adjust_contrast(&mut self.image, op.contrast);
+        Ok(())
     }
 }
 
 impl SpecTransform<&Flipv> for Photon {
-    fn transform(&mut self, _op: &Flipv) {
-        transform::flipv(&mut self.0)
+    fn transform(&mut self, _op: &Flipv) -> Result<(), EngineError> {
+        transform::flipv(&mut self.image);
+        Ok(())
     }
 }
 
 impl SpecTransform<&Fliph> for Photon {
-    fn transform(&mut self, _op: &Fliph) {
-        transform::fliph(&mut self.0)
+    fn transform(&mut self, _op: &Fliph) -> Result<(), EngineError> {
+        transform::fliph(&mut self.image);
+        Ok(())
     }
 }
 
 impl SpecTransform<&Filter> for Photon {
-    fn transform(&mut self, op: &Filter) {
-        match filter::Filter::from_i32(op.filter) {
-            Some(filter::Filter::Unspecified) => {}
-            Some(f) => filters::filter(&mut self.0, f.to_str().unwrap()),
-            _ => {}
+    fn transform(&mut self, op: &Filter) -> Result<(), EngineError> {
+        let filter = filter::Filter::from_i32(op.filter).ok_or(EngineError::InvalidEnumValue {
+            field: "filter",
+            value: op.filter,
+        })?;
+        if let Some(name) = filter.to_str() {
+            filters::filter(&mut self.image, name);
         }
+        Ok(())
     }
 }
 
 impl SpecTransform<&Resize> for Photon {
-    fn transform(&mut self, op: &Resize) {
-        let img = match resize::ResizeType::from_i32(op.rtype).unwrap() {
-            resize::ResizeType::Normal => transform::resize(
-                &self.0,
-                op.width,
-                op.height,
-                resize::SampleFilter::from_i32(op.filter).unwrap().into(),
-            ),
-            resize::ResizeType::SeamCarve => transform::seam_carve(&self.0, op.width, op.height),
+    fn transform(&mut self, op: &Resize) -> Result<(), EngineError> {
+        if op.width == 0 && op.height == 0 {
+            return Err(EngineError::ZeroSizeResize {
+                width: op.width,
+                height: op.height,
+            });
+        }
+        let rtype =
+            resize::ResizeType::from_i32(op.rtype).ok_or(EngineError::InvalidEnumValue {
+                field: "resize.rtype",
+                value: op.rtype,
+            })?;
+        let fit = resize::Fit::from_i32(op.fit).ok_or(EngineError::InvalidEnumValue {
+            field: "resize.fit",
+            value: op.fit,
+        })?;
+        let gravity = Gravity::from_i32(op.gravity).ok_or(EngineError::InvalidEnumValue {
+            field: "resize.gravity",
+            value: op.gravity,
+        })?;
+
+        // 只给出一边时，按原图比例算出另一边，这时不存在比例不一致的问题
+        let (src_width, src_height) = (self.image.get_width(), self.image.get_height());
+        let (width, height, fit) = match (op.width, op.height) {
+            (w, 0) => (w, scale(src_height, w, src_width), resize::Fit::Fill),
+            (0, h) => (scale(src_width, h, src_height), h, resize::Fit::Fill),
+            (w, h) => (w, h, fit),
         };
-        self.0 = img;
+        // 分配内存之前先检查目标尺寸，防止很小的图片被放大到占用大量内存
+        self.limits.check_size(width, height)?;
+
+        let img = match rtype {
+            resize::ResizeType::Normal => {
+                let filter = resize::SampleFilter::from_i32(op.filter).ok_or(
+                    EngineError::InvalidEnumValue {
+                        field: "resize.filter",
+                        value: op.filter,
+                    },
+                )?;
+                let filter = filter.into();
+                match fit {
+                    resize::Fit::Fill => transform::resize(&self.image, width, height, filter),
+                    resize::Fit::Inside => {
+                        let (w, h) = fit_size(src_width, src_height, width, height, false);
+                        transform::resize(&self.image, w, h, filter)
+                    }
+                    resize::Fit::Contain => {
+                        let (w, h) = fit_size(src_width, src_height, width, height, false);
+                        let img = transform::resize(&self.image, w, h, filter);
+                        let (x, y) = gravity.offset(width - w, height - h);
+                        let background = op.background.to_be_bytes();
+                        paste(&img, width, height, x, y, background)
+                    }
+                    resize::Fit::Cover => {
+                        // cover 先缩放到比目标更大的尺寸再截取，中间结果也需要检查
+                        let (w, h) = fit_size(src_width, src_height, width, height, true);
+                        self.limits.check_size(w, h)?;
+                        let mut img = transform::resize(&self.image, w, h, filter);
+                        let (x, y) = gravity.offset(w - width, h - height);
+                        transform::crop(&mut img, x, y, x + width, y + height)
+                    }
+                }
+            }
+            resize::ResizeType::SeamCarve => transform::seam_carve(&self.image, width, height),
+        };
+        self.image = img;
+        Ok(())
+    }
+}
+
+// 按 numerator / denominator 的比例缩放 value，至少为 1
+fn scale(value: u32, numerator: u32, denominator: u32) -> u32 {
+    // 超出 u32 范围时 as 会截断到 u32::MAX，之后由尺寸限制拒绝
+    let scaled = (value as f64 * numerator as f64 / denominator as f64).round();
+    (scaled as u32).max(1)
+}
+
+// 保持比例缩放 width x height，cover 为 true 时刚好覆盖目标尺寸，否则刚好放进目标尺寸
+fn fit_size(
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+    cover: bool,
+) -> (u32, u32) {
+    let scale_x = target_width as f64 / width as f64;
+    let scale_y = target_height as f64 / height as f64;
+    let ratio = if cover {
+        scale_x.max(scale_y)
+    } else {
+        scale_x.min(scale_y)
+    };
+    let w = ((width as f64 * ratio).round() as u32).max(1);
+    let h = ((height as f64 * ratio).round() as u32).max(1);
+    // 舍入误差可能让结果差一个像素，需要修正
+    if cover {
+        (w.max(target_width), h.max(target_height))
+    } else {
+        (w.min(target_width), h.min(target_height))
     }
 }
 
+// 生成 width x height 的画布，填充 background 后把图片放在 (x, y) 处
+fn paste(
+    image: &PhotonImage,
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    background: [u8; 4],
+) -> PhotonImage {
+    let mut canvas = background.repeat(width as usize * height as usize);
+    let pixels = image.get_raw_pixels();
+    let row = image.get_width() as usize * 4;
+    for (i, line) in pixels.chunks(row).enumerate() {
+        let start = (((y as usize + i) * width as usize) + x as usize) * 4;
+        canvas[start..start + row].copy_from_slice(line);
+    }
+    PhotonImage::new(canvas, width, height)
+}
+
 impl SpecTransform<&Watermark> for Photon {
-    fn transform(&mut self, op: &Watermark) {
-        multiple::watermark(&mut self.0, &WATERMARK, op.x, op.y);
+    fn transform(&mut self, op: &Watermark) -> Result<(), EngineError> {
+        let text;
+        let mark = match op.source {
+            None => &*WATERMARK,
+            Some(watermark::Source::Name(ref name)) => {
+                self.watermarks
+                    .get(name)
+                    .ok_or(EngineError::InvalidParameter {
+                        field: "watermark.name",
+                        reason: "is not a registered watermark",
+                    })?
+            }
+            Some(watermark::Source::Url(ref url)) => {
+                self.url_watermarks
+                    .get(url)
+                    .ok_or(EngineError::InvalidParameter {
+                        field: "watermark.url",
+                        reason: "has not been fetched",
+                    })?
+            }
+            Some(watermark::Source::Text(ref op)) => {
+                text = render_text(op, self.limits)?;
+                &text
+            }
+        };
+        stamp(&mut self.image, mark, op, self.limits)
+    }
+}
+
+impl SpecTransform<&Format> for Photon {
+    fn transform(&mut self, op: &Format) -> Result<(), EngineError> {
+        if format::Type::from_i32(op.ftype).is_none() {
+            return Err(EngineError::InvalidEnumValue {
+                field: "format.ftype",
+                value: op.ftype,
+            });
+        }
+        // 多次指定时以最后一个为准
+        if let Some(format) = op.to_output_format(self.default_quality) {
+            self.format = Some(format);
+        }
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Brightness> for Photon {
+    fn transform(&mut self, op: &Brightness) -> Result<(), EngineError> {
+        if !(-255..=255).contains(&op.brightness) {
+            return Err(EngineError::InvalidParameter {
+                field: "brightness",
+                reason: "must be between -255 and 255",
+            });
+        }
+        let delta = op.brightness.unsigned_abs() as u8;
+        if op.brightness > 0 {
+            effects::inc_brightness(&mut self.image, delta);
+        } else if op.brightness < 0 {
+            effects::dec_brightness(&mut self.image, delta);
+        }
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Saturation> for Photon {
+    fn transform(&mut self, op: &Saturation) -> Result<(), EngineError> {
+        if !(-1.0..=1.0).contains(&op.amount) {
+            return Err(EngineError::InvalidParameter {
+                field: "saturation.amount",
+                reason: "must be between -1.0 and 1.0",
+            });
+        }
+        if op.amount > 0.0 {
+            colour_spaces::saturate_hsl(&mut self.image, op.amount);
+        } else if op.amount < 0.0 {
+            colour_spaces::desaturate_hsl(&mut self.image, -op.amount);
+        }
+        Ok(())
+    }
+}
+
+impl SpecTransform<&HueRotate> for Photon {
+    fn transform(&mut self, op: &HueRotate) -> Result<(), EngineError> {
+        if !op.degrees.is_finite() {
+            return Err(EngineError::InvalidParameter {
+                field: "hue_rotate.degrees",
+                reason: "must be a finite number",
+            });
+        }
+        // photon 的参数是 0-1 之间的比例，1 表示转一整圈
+        colour_spaces::hue_rotate_hsl(&mut self.image, op.degrees.rem_euclid(360.0) / 360.0);
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Blur> for Photon {
+    fn transform(&mut self, op: &Blur) -> Result<(), EngineError> {
+        // 半径越大越耗 CPU，需要限制
+        if !(1..=MAX_BLUR_RADIUS).contains(&op.radius) {
+            return Err(EngineError::InvalidParameter {
+                field: "blur.radius",
+                reason: "must be between 1 and 100",
+            });
+        }
+        conv::gaussian_blur(&mut self.image, op.radius);
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Sharpen> for Photon {
+    fn transform(&mut self, _op: &Sharpen) -> Result<(), EngineError> {
+        conv::sharpen(&mut self.image);
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Grayscale> for Photon {
+    fn transform(&mut self, _op: &Grayscale) -> Result<(), EngineError> {
+        monochrome::grayscale(&mut self.image);
+        Ok(())
+    }
+}
+
+impl SpecTransform<&Rotate> for Photon {
+    fn transform(&mut self, op: &Rotate) -> Result<(), EngineError> {
+        if !op.degrees.is_finite() {
+            return Err(EngineError::InvalidParameter {
+                field: "rotate.degrees",
+                reason: "must be a finite number",
+            });
+        }
+        let degrees = op.degrees.rem_euclid(360.0);
+        let (width, height) = (self.image.get_width(), self.image.get_height());
+        // 90 度的整数倍走无损的路径，不需要插值也不改变画布面积
+        let lossless = degrees % 90.0 == 0.0;
+        if !lossless {
+            // 每转 45 度面积就会翻倍，连续旋转时画布会越来越大
+            let (w, h) = rotate::expanded_size(width, height, degrees);
+            self.limits.check_size(w, h)?;
+        }
+        let pixels = self.image.get_raw_pixels();
+        let (pixels, width, height) = if lossless {
+            rotate::rotate_quarters(&pixels, width, height, (degrees / 90.0) as u32)
+        } else {
+            let background = op.background.to_be_bytes();
+            rotate::rotate_any(&pixels, width, height, degrees, background)
+        };
+        self.image = PhotonImage::new(pixels, width, height);
+        Ok(())
     }
 }
 
 // photon 库竟然没有提供在内存中对图片转换格式的方法，只好手工实现
-fn image_to_buf(img: PhotonImage, format: ImageOutputFormat) -> Vec<u8> {
+fn image_to_buf(img: PhotonImage, format: OutputFormat) -> Result<Vec<u8>, EngineError> {
     let raw_pixels = img.get_raw_pixels();
     let width = img.get_width();
     let height = img.get_height();
 
-    let img_buffer = ImageBuffer::from_vec(width, height, raw_pixels).unwrap();
+    let mut buffer = Vec::with_capacity(32768);
+    let format = match format {
+        OutputFormat::Png => ImageOutputFormat::Png,
+        OutputFormat::Jpeg(quality) => ImageOutputFormat::Jpeg(quality),
+        OutputFormat::Gif => ImageOutputFormat::Gif,
+        OutputFormat::Bmp => ImageOutputFormat::Bmp,
+        // image 0.23 不支持 WebP 编码，使用 image-webp 生成（无损）
+        OutputFormat::WebP => {
+            WebPEncoder::new(&mut buffer)
+                .encode(&raw_pixels, width, height, ColorType::Rgba8)
+                .map_err(|e| EngineError::Encode(e.to_string()))?;
+            return Ok(buffer);
+        }
+    };
+
+    let img_buffer = ImageBuffer::from_vec(width, height, raw_pixels).ok_or_else(|| {
+        EngineError::Encode(format!("pixel buffer does not match {}x{}", width, height))
+    })?;
     let dynimage = DynamicImage::ImageRgba8(img_buffer);
 
-    let mut buffer = Vec::with_capacity(32768);
-    dynimage.write_to(&mut buffer, format).unwrap();
-    buffer
+    dynimage
+        .write_to(&mut buffer, format)
+        .map_err(|e| EngineError::Encode(e.to_string()))?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        metadata::tests::{exif_with_orientation, jpeg},
+        DecodeLimits,
+    };
+    use super::*;
+
+    fn blank(width: u32, height: u32) -> Photon {
+        let pixels = vec![255; (width * height * 4) as usize];
+        Photon {
+            image: PhotonImage::new(pixels, width, height),
+            format: None,
+            default_quality: DEFAULT_JPEG_QUALITY,
+            exif: None,
+            watermarks: Arc::default(),
+            url_watermarks: Watermarks::default(),
+            limits: DecodeLimits::default(),
+        }
+    }
+
+    #[test]
+    fn crop_out_of_bounds_should_fail() {
+        let mut engine = blank(10, 10);
+        let crop = Crop {
+            x1: 5,
+            y1: 5,
+            x2: 20,
+            y2: 8,
+        };
+        assert!(matches!(
+            engine.transform(&crop),
+            Err(EngineError::CropOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn smart_and_focal_crop_should_return_requested_size() {
+        let specs = [
+            Spec::new_smart_crop(4, 6),
+            Spec::new_focal_crop(4, 6, 0.9, 0.1),
+        ];
+        for spec in specs {
+            assert_eq!(resized(10, 10, spec), (4, 6));
+        }
+
+        let invalid = [
+            Spec::new_smart_crop(0, 6),
+            Spec::new_smart_crop(20, 6),
+            Spec::new_focal_crop(20, 6, 0.5, 0.5),
+        ];
+        for spec in invalid {
+            let mut engine = blank(10, 10);
+            assert!(matches!(
+                engine.apply(&[spec]),
+                Err(EngineError::CropOutOfBounds { .. })
+            ));
+        }
+        let mut engine = blank(10, 10);
+        assert!(matches!(
+            engine.apply(&[Spec::new_focal_crop(4, 6, 1.5, f32::NAN)]),
+            Err(EngineError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn named_watermark_should_be_looked_up() {
+        let mut named = Watermarks::default();
+        let logo = include_bytes!("../../rust-logo.png");
+        named.insert("logo", logo, DecodeLimits::default()).unwrap();
+        let mut engine = blank(100, 100).with_watermarks(Arc::new(named), Watermarks::default());
+
+        let mut op = Watermark {
+            scale: 0.5,
+            source: Some(watermark::Source::Name("logo".to_owned())),
+            ..Default::default()
+        };
+        engine.transform(&op).unwrap();
+        assert_ne!(engine.image.get_raw_pixels(), vec![255; 100 * 100 * 4]);
+
+        op.source = Some(watermark::Source::Name("missing".to_owned()));
+        assert!(matches!(
+            engine.transform(&op),
+            Err(EngineError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_enum_value_should_fail() {
+        let mut engine = blank(10, 10);
+        let resize = Resize {
+            width: 5,
+            height: 5,
+            rtype: 42,
+            ..Default::default()
+        };
+        assert!(matches!(
+            engine.transform(&resize),
+            Err(EngineError::InvalidEnumValue { value: 42, .. })
+        ));
+    }
+
+    #[test]
+    fn zero_size_resize_should_fail() {
+        let mut engine = blank(10, 10);
+        let spec = Spec::new_resize(0, 0, resize::SampleFilter::Nearest);
+        assert!(matches!(
+            engine.apply(&[spec]),
+            Err(EngineError::ZeroSizeResize { .. })
+        ));
+    }
+
+    #[test]
+    fn oversized_resize_should_fail() {
+        let limits = DecodeLimits {
+            max_width: 100,
+            max_height: 100,
+            max_pixels: 5000,
+        };
+        let nearest = resize::SampleFilter::Nearest;
+        let cases = [
+            ((10, 1), Spec::new_resize(200, 10, nearest)),
+            ((10, 1), Spec::new_resize(80, 80, nearest)),
+            // 按比例算出来的宽度 10 * 90 = 900 超出限制
+            ((10, 1), Spec::new_resize(0, 90, nearest)),
+            // 目标尺寸没有超出，但 cover 的中间结果是 50x500
+            (
+                (1, 10),
+                Spec::new_resize_fit(50, 50, resize::Fit::Cover, Gravity::Center, nearest),
+            ),
+        ];
+        for ((width, height), spec) in cases {
+            let mut engine = blank(width, height).with_limits(limits);
+            assert!(matches!(
+                engine.apply(&[spec]),
+                Err(EngineError::ImageTooLarge { .. })
+            ));
+        }
+
+        let mut engine = blank(10, 1).with_limits(limits);
+        engine.apply(&[Spec::new_resize(0, 5, nearest)]).unwrap();
+        assert_eq!(engine.image.get_width(), 50);
+    }
+
+    #[test]
+    fn oversized_rotate_should_fail() {
+        let limits = DecodeLimits {
+            max_width: 100,
+            max_height: 100,
+            max_pixels: 5000,
+        };
+        // 60x60 转 45 度变成 85x85，超过了像素数的限制
+        let mut engine = blank(60, 60).with_limits(limits);
+        assert!(matches!(
+            engine.apply(&[Spec::new_rotate(45.0, 0)]),
+            Err(EngineError::ImageTooLarge { .. })
+        ));
+
+        // 连续旋转时每一步都要检查
+        let mut engine = blank(40, 40).with_limits(limits);
+        let specs = vec![Spec::new_rotate(45.0, 0); 3];
+        assert!(matches!(
+            engine.apply(&specs),
+            Err(EngineError::ImageTooLarge { .. })
+        ));
+
+        // 90 度的整数倍不改变面积
+        let mut engine = blank(70, 70).with_limits(limits);
+        engine.apply(&[Spec::new_rotate(90.0, 0)]).unwrap();
+    }
+
+    fn resized(width: u32, height: u32, spec: Spec) -> (u32, u32) {
+        let mut engine = blank(width, height);
+        engine.apply(&[spec]).unwrap();
+        (engine.image.get_width(), engine.image.get_height())
+    }
+
+    #[test]
+    fn resize_should_respect_fit_mode() {
+        let nearest = resize::SampleFilter::Nearest;
+        let fit = |fit| Spec::new_resize_fit(50, 50, fit, Gravity::Center, nearest);
+        assert_eq!(resized(200, 100, fit(resize::Fit::Fill)), (50, 50));
+        assert_eq!(resized(200, 100, fit(resize::Fit::Contain)), (50, 50));
+        assert_eq!(resized(200, 100, fit(resize::Fit::Cover)), (50, 50));
+        assert_eq!(resized(200, 100, fit(resize::Fit::Inside)), (50, 25));
+
+        // 0 表示按比例自动计算
+        assert_eq!(
+            resized(200, 100, Spec::new_resize(50, 0, nearest)),
+            (50, 25)
+        );
+        assert_eq!(
+            resized(200, 100, Spec::new_resize(0, 50, nearest)),
+            (100, 50)
+        );
+    }
+
+    #[test]
+    fn contain_should_fill_background_by_gravity() {
+        let mut engine = blank(20, 10);
+        let spec = Resize {
+            width: 10,
+            height: 10,
+            fit: resize::Fit::Contain as i32,
+            gravity: Gravity::North as i32,
+            background: 0xff0000ff,
+            ..Default::default()
+        };
+        engine.transform(&spec).unwrap();
+        let pixels = engine.image.get_raw_pixels();
+        // 图片缩放成 10x5 放在上半部分，下半部分是红色背景
+        assert_eq!(pixels[0..4], [255, 255, 255, 255]);
+        assert_eq!(pixels[pixels.len() - 4..], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn valid_specs_should_generate_image() {
+        let mut engine = blank(10, 10);
+        let specs = vec![
+            Spec::new_resize(4, 4, resize::SampleFilter::Nearest),
+            Spec::new_format(format::Type::Png, 0),
+        ];
+        engine.apply(&specs).unwrap();
+        let (data, format) = engine.generate(OutputFormat::default()).unwrap();
+        assert_eq!(format, OutputFormat::Png);
+        assert!(!data.is_empty());
+    }
+
+    #[test]
+    fn color_adjustments_should_be_applied() {
+        let mut engine = blank(10, 10);
+        let specs = vec![
+            Spec::new_brightness(-40),
+            Spec::new_saturation(0.5),
+            Spec::new_hue_rotate(-90.0),
+            Spec::new_blur(2),
+            Spec::new_sharpen(),
+            Spec::new_grayscale(),
+        ];
+        engine.apply(&specs).unwrap();
+        let pixels = engine.image.get_raw_pixels();
+        // 灰度图每个像素的 rgb 都相等，并且比原来的白色暗
+        assert!(pixels.chunks(4).all(|p| p[0] == p[1] && p[1] == p[2]));
+        assert!(pixels[0] < 255);
+    }
+
+    #[test]
+    fn hue_rotate_should_shift_by_degrees() {
+        let pixels = [255, 0, 0, 255].repeat(4);
+        let mut engine = blank(2, 2);
+        engine.image = PhotonImage::new(pixels, 2, 2);
+        engine.apply(&[Spec::new_hue_rotate(120.0)]).unwrap();
+        // 纯红色转 120 度变成纯绿色
+        for p in engine.image.get_raw_pixels().chunks(4) {
+            assert!(p[0] < 10 && p[1] > 245 && p[2] < 10, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn rotate_should_change_dimensions() {
+        let mut engine = blank(20, 10);
+        engine.apply(&[Spec::new_rotate(-90.0, 0)]).unwrap();
+        assert_eq!(engine.image.get_width(), 10);
+        assert_eq!(engine.image.get_height(), 20);
+
+        // 任意角度会扩大画布，角落用背景色填充
+        let mut engine = blank(10, 10);
+        engine.apply(&[Spec::new_rotate(30.0, 0xff0000ff)]).unwrap();
+        assert!(engine.image.get_width() > 10);
+        assert_eq!(engine.image.get_raw_pixels()[0..4], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn exif_orientation_should_be_applied_on_decode() {
+        let data = exif_with_orientation(6).embed_in_jpeg(jpeg(20, 10));
+        let engine = Photon::try_from(Bytes::from(data)).unwrap();
+        assert_eq!(engine.image.get_width(), 10);
+        assert_eq!(engine.image.get_height(), 20);
+    }
+
+    #[test]
+    fn metadata_should_be_stripped_unless_kept() {
+        let data = Bytes::from(exif_with_orientation(6).embed_in_jpeg(jpeg(20, 10)));
+        let format = OutputFormat::Jpeg(80);
+
+        let engine = Photon::try_from(data.clone()).unwrap().with_metadata(false);
+        let (image, _) = engine.generate(format).unwrap();
+        assert!(Exif::read(&image).is_none());
+
+        // 保留的 EXIF 里 orientation 已经改成了 1，不会被再次旋转
+        let engine = Photon::try_from(data).unwrap().with_metadata(true);
+        let (image, _) = engine.generate(format).unwrap();
+        assert_eq!(Exif::read(&image).unwrap().orientation, 1);
+    }
+
+    #[test]
+    fn out_of_range_adjustments_should_fail() {
+        let specs = [
+            Spec::new_brightness(300),
+            Spec::new_saturation(1.5),
+            Spec::new_saturation(f32::NAN),
+            Spec::new_hue_rotate(f32::INFINITY),
+            Spec::new_blur(0),
+            Spec::new_blur(1000),
+            Spec::new_rotate(f32::NAN, 0),
+        ];
+        for spec in specs {
+            let mut engine = blank(10, 10);
+            assert!(matches!(
+                engine.apply(&[spec]),
+                Err(EngineError::InvalidParameter { .. })
+            ));
+        }
+    }
 }
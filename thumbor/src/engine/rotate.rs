@@ -0,0 +1,143 @@
+// photon 0.3 没有提供旋转，这里直接对 RGBA 像素做处理
+// 所有函数的输入输出都是 (像素, 宽, 高)，旋转方向为顺时针
+
+pub(super) type Pixels = (Vec<u8>, u32, u32);
+
+// 按 90 度的整数倍旋转，只是像素位置的重排，不会有任何损失
+// quarters 为顺时针旋转的次数
+pub(super) fn rotate_quarters(pixels: &[u8], width: u32, height: u32, quarters: u32) -> Pixels {
+    let (w, h) = (width as usize, height as usize);
+    let quarters = quarters % 4;
+    if quarters == 0 {
+        return (pixels.to_vec(), width, height);
+    }
+
+    let (new_w, new_h) = if quarters == 2 { (w, h) } else { (h, w) };
+    let mut out = vec![0; pixels.len()];
+    for y in 0..new_h {
+        for x in 0..new_w {
+            // 目标位置 (x, y) 对应的原图位置
+            let (sx, sy) = match quarters {
+                1 => (y, h - 1 - x),
+                2 => (w - 1 - x, h - 1 - y),
+                _ => (w - 1 - y, x),
+            };
+            let src = (sy * w + sx) * 4;
+            let dst = (y * new_w + x) * 4;
+            out[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+        }
+    }
+    (out, new_w as u32, new_h as u32)
+}
+
+// 任意角度旋转之后能容纳整张图的画布尺寸
+pub(super) fn expanded_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (w, h) = (width as f64, height as f64);
+    let new_w = (w * cos.abs() + h * sin.abs()).round().max(1.0) as u32;
+    let new_h = (w * sin.abs() + h * cos.abs()).round().max(1.0) as u32;
+    (new_w, new_h)
+}
+
+// 任意角度旋转：画布扩大到能容纳旋转后的整张图，空出来的部分用 background 填充
+// 对每个目标像素反向找到原图中的位置，再做双线性插值
+pub(super) fn rotate_any(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    degrees: f32,
+    background: [u8; 4],
+) -> Pixels {
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (w, h) = (width as f64, height as f64);
+    let (new_w, new_h) = expanded_size(width, height, degrees);
+    let (cx, cy) = (w / 2.0, h / 2.0);
+    let (ncx, ncy) = (new_w as f64 / 2.0, new_h as f64 / 2.0);
+
+    let mut out = Vec::with_capacity(new_w as usize * new_h as usize * 4);
+    for y in 0..new_h {
+        for x in 0..new_w {
+            // 以像素中心为准，相对画布中心逆时针转回去
+            let dx = x as f64 + 0.5 - ncx;
+            let dy = y as f64 + 0.5 - ncy;
+            let sx = dx * cos + dy * sin + cx - 0.5;
+            let sy = -dx * sin + dy * cos + cy - 0.5;
+            out.extend_from_slice(&sample(pixels, width, height, sx, sy, background));
+        }
+    }
+    (out, new_w, new_h)
+}
+
+// 在 (x, y) 处做双线性插值，落在原图之外的点取 background
+fn sample(pixels: &[u8], width: u32, height: u32, x: f64, y: f64, background: [u8; 4]) -> [u8; 4] {
+    let pixel = |px: i64, py: i64| -> [f64; 4] {
+        if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+            return background.map(f64::from);
+        }
+        let i = ((py as usize) * width as usize + px as usize) * 4;
+        [
+            pixels[i] as f64,
+            pixels[i + 1] as f64,
+            pixels[i + 2] as f64,
+            pixels[i + 3] as f64,
+        ]
+    };
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let (p00, p10) = (pixel(x0, y0), pixel(x0 + 1, y0));
+    let (p01, p11) = (pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1));
+
+    let mut result = [0; 4];
+    for (c, v) in result.iter_mut().enumerate() {
+        let top = p00[c] * (1.0 - fx) + p10[c] * fx;
+        let bottom = p01[c] * (1.0 - fx) + p11[c] * fx;
+        *v = (top * (1.0 - fy) + bottom * fy).round().clamp(0.0, 255.0) as u8;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x1 的图片，左边红色，右边蓝色
+    fn red_blue() -> Vec<u8> {
+        vec![255, 0, 0, 255, 0, 0, 255, 255]
+    }
+
+    #[test]
+    fn quarter_rotation_should_be_lossless() {
+        let pixels = red_blue();
+        // 顺时针转 90 度之后，红色在上，蓝色在下
+        let (out, w, h) = rotate_quarters(&pixels, 2, 1, 1);
+        assert_eq!((w, h), (1, 2));
+        assert_eq!(out, pixels);
+
+        let (out, w, h) = rotate_quarters(&pixels, 2, 1, 2);
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(out, vec![0, 0, 255, 255, 255, 0, 0, 255]);
+
+        let (out, w, h) = rotate_quarters(&pixels, 2, 1, 3);
+        assert_eq!((w, h), (1, 2));
+        assert_eq!(out, vec![0, 0, 255, 255, 255, 0, 0, 255]);
+
+        // 转四次回到原样
+        let (out, ..) = rotate_quarters(&pixels, 2, 1, 4);
+        assert_eq!(out, pixels);
+    }
+
+    #[test]
+    fn arbitrary_rotation_should_expand_canvas() {
+        let pixels = vec![255; 10 * 10 * 4];
+        let background = [0, 255, 0, 255];
+        let (out, w, h) = rotate_any(&pixels, 10, 10, 45.0, background);
+        assert_eq!((w, h), (14, 14));
+        assert_eq!(out.len(), (w * h * 4) as usize);
+        // 四个角落在原图之外，中心仍然是原图
+        assert_eq!(out[0..4], background);
+        let center = ((7 * w + 7) * 4) as usize;
+        assert_eq!(out[center..center + 4], [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,134 @@
+// 根据图片内容选择最显著的区域：边缘越多的地方细节越丰富，越可能是主体
+// 大图先按块求平均缩小到 GRID 以内再计算，避免占用太多内存和 CPU
+const GRID: u32 = 256;
+
+// 返回 crop_width x crop_height 的截取窗口的左上角坐标，调用者保证窗口不超过图片大小
+pub(super) fn salient_region(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    crop_width: u32,
+    crop_height: u32,
+) -> (u32, u32) {
+    let factor = width.max(height).div_ceil(GRID).max(1);
+    let gw = width.div_ceil(factor) as usize;
+    let gh = height.div_ceil(factor) as usize;
+
+    // 每个格子里像素亮度的平均值
+    let mut sum = vec![0u64; gw * gh];
+    let mut count = vec![0u64; gw * gh];
+    for (i, p) in pixels.chunks_exact(4).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let cell = (y / factor) as usize * gw + (x / factor) as usize;
+        sum[cell] += (p[0] as u64 * 299 + p[1] as u64 * 587 + p[2] as u64 * 114) / 1000;
+        count[cell] += 1;
+    }
+    let luma: Vec<i64> = sum
+        .iter()
+        .zip(&count)
+        .map(|(s, c)| (s / (*c).max(1)) as i64)
+        .collect();
+
+    // 边缘强度：水平和垂直方向上亮度变化的绝对值之和，同时累加成积分图
+    let at = |x: usize, y: usize| luma[y * gw + x];
+    let mut integral = vec![0i64; (gw + 1) * (gh + 1)];
+    for y in 0..gh {
+        let mut row = 0;
+        for x in 0..gw {
+            let dx = at((x + 1).min(gw - 1), y) - at(x.saturating_sub(1), y);
+            let dy = at(x, (y + 1).min(gh - 1)) - at(x, y.saturating_sub(1));
+            row += dx.abs() + dy.abs();
+            integral[(y + 1) * (gw + 1) + x + 1] = integral[y * (gw + 1) + x + 1] + row;
+        }
+    }
+    let area = |x: usize, y: usize, w: usize, h: usize| {
+        let (x2, y2) = (x + w, y + h);
+        integral[y2 * (gw + 1) + x2] - integral[y * (gw + 1) + x2] - integral[y2 * (gw + 1) + x]
+            + integral[y * (gw + 1) + x]
+    };
+
+    // 找到边缘强度之和最大的窗口，分数相同时选更靠近中心的
+    let cw = ((crop_width / factor) as usize).clamp(1, gw);
+    let ch = ((crop_height / factor) as usize).clamp(1, gh);
+    let distance = |x: usize, y: usize| {
+        let dx = (2 * x + cw) as i64 - gw as i64;
+        let dy = (2 * y + ch) as i64 - gh as i64;
+        dx * dx + dy * dy
+    };
+    let mut best = (0, 0);
+    let mut best_score = (i64::MIN, i64::MIN);
+    for y in 0..=gh - ch {
+        for x in 0..=gw - cw {
+            let score = (area(x, y, cw, ch), -distance(x, y));
+            if score > best_score {
+                best_score = score;
+                best = (x, y);
+            }
+        }
+    }
+
+    let x = (best.0 as u32 * factor).min(width - crop_width);
+    let y = (best.1 as u32 * factor).min(height - crop_height);
+    (x, y)
+}
+
+// 以焦点为中心的截取窗口，focal_x/focal_y 是相对于图片宽高的比例，窗口不会超出图片
+pub(super) fn focal_region(
+    width: u32,
+    height: u32,
+    crop_width: u32,
+    crop_height: u32,
+    focal_x: f32,
+    focal_y: f32,
+) -> (u32, u32) {
+    let start = |size: u32, crop: u32, focal: f32| {
+        let center = (size as f64 * focal as f64).round() as i64;
+        (center - crop as i64 / 2).clamp(0, (size - crop) as i64) as u32
+    };
+    (
+        start(width, crop_width, focal_x),
+        start(height, crop_height, focal_y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 黑色背景上有一个 8x8 的白色方块
+    fn square(width: u32, height: u32, x: u32, y: u32) -> Vec<u8> {
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        for py in y..y + 8 {
+            for px in x..x + 8 {
+                let i = ((py * width + px) * 4) as usize;
+                pixels[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn smart_crop_should_find_detailed_region() {
+        let pixels = square(40, 40, 28, 28);
+        let (x, y) = salient_region(&pixels, 40, 40, 12, 12);
+        assert!((25..=28).contains(&x) && (25..=28).contains(&y));
+
+        // 大图会先缩小再计算，结果仍然在方块附近
+        let pixels = square(600, 300, 500, 40);
+        let (x, y) = salient_region(&pixels, 600, 300, 40, 40);
+        assert!((470..=510).contains(&x) && (10..=50).contains(&y));
+    }
+
+    #[test]
+    fn smart_crop_of_plain_image_should_be_centered() {
+        let pixels = vec![255; 40 * 20 * 4];
+        assert_eq!(salient_region(&pixels, 40, 20, 10, 10), (15, 5));
+    }
+
+    #[test]
+    fn focal_crop_should_stay_inside_image() {
+        assert_eq!(focal_region(100, 50, 20, 20, 0.5, 0.5), (40, 15));
+        assert_eq!(focal_region(100, 50, 20, 20, 0.0, 1.0), (0, 30));
+        assert_eq!(focal_region(100, 50, 100, 50, 0.9, 0.1), (0, 0));
+    }
+}
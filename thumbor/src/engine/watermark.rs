@@ -0,0 +1,344 @@
+use super::{DecodeLimits, EngineError};
+use crate::pb::{watermark, Gravity, Watermark};
+use photon_rs::{multiple, native::open_image_from_bytes, text, transform, PhotonImage};
+use std::collections::HashMap;
+
+// photon 只能用固定的字号画白色的文字，需要自己缩放和着色
+const PHOTON_FONT_SIZE: u32 = 90;
+const DEFAULT_FONT_SIZE: u32 = 24;
+const MAX_FONT_SIZE: u32 = 512;
+const MAX_TEXT_LEN: usize = 256;
+
+// 解码好的水印图片，可以按配置里注册的名字或者 url 查找
+#[derive(Default)]
+pub struct Watermarks {
+    images: HashMap<String, PhotonImage>,
+}
+
+impl Watermarks {
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        data: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<(), EngineError> {
+        limits.check(data)?;
+        let image = open_image_from_bytes(data).map_err(|e| EngineError::Decode(e.to_string()))?;
+        self.images.insert(key.into(), image);
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PhotonImage> {
+        self.images.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+}
+
+// 把文字画成透明背景的水印图片，缩放后的尺寸不能超过 limits
+pub(super) fn render_text(
+    op: &watermark::Text,
+    limits: DecodeLimits,
+) -> Result<PhotonImage, EngineError> {
+    let len = op.content.chars().count();
+    if len == 0 || len > MAX_TEXT_LEN {
+        return Err(EngineError::InvalidParameter {
+            field: "watermark.text.content",
+            reason: "must contain 1 to 256 characters",
+        });
+    }
+    let size = match op.size {
+        0 => DEFAULT_FONT_SIZE,
+        size if size <= MAX_FONT_SIZE => size,
+        _ => {
+            return Err(EngineError::InvalidParameter {
+                field: "watermark.text.size",
+                reason: "must not exceed 512",
+            })
+        }
+    };
+
+    // 先按 photon 的字号画在足够大的黑色透明画布上，再裁掉空白
+    let width = PHOTON_FONT_SIZE * len as u32;
+    let height = PHOTON_FONT_SIZE * 2;
+    let mut canvas = PhotonImage::new(vec![0; width as usize * height as usize * 4], width, height);
+    text::draw_text(&mut canvas, &op.content, 0, 0);
+    let (x1, y1, x2, y2) = opaque_bounds(&canvas).ok_or(EngineError::InvalidParameter {
+        field: "watermark.text.content",
+        reason: "has no visible characters",
+    })?;
+    let glyphs = transform::crop(&mut canvas, x1, y1, x2, y2);
+
+    let w = ((x2 - x1) * size / PHOTON_FONT_SIZE).max(1);
+    let h = ((y2 - y1) * size / PHOTON_FONT_SIZE).max(1);
+    limits.check_size(w, h)?;
+    let glyphs = transform::resize(&glyphs, w, h, transform::SamplingFilter::Triangle);
+
+    // 白色文字画在黑色上，红色通道就是文字的覆盖率，用它作为 alpha 给文字着色
+    let [r, g, b, a] = match op.color {
+        0 => [255; 4],
+        color => color.to_be_bytes(),
+    };
+    let mut pixels = glyphs.get_raw_pixels();
+    for p in pixels.chunks_exact_mut(4) {
+        let coverage = p[0] as u32;
+        p.copy_from_slice(&[r, g, b, (coverage * a as u32 / 255) as u8]);
+    }
+    Ok(PhotonImage::new(pixels, w, h))
+}
+
+// 按照 op 指定的不透明度、缩放比例和摆放方式把水印叠加到图片上
+pub(super) fn stamp(
+    image: &mut PhotonImage,
+    mark: &PhotonImage,
+    op: &Watermark,
+    limits: DecodeLimits,
+) -> Result<(), EngineError> {
+    let opacity = match op.opacity {
+        v if v == 0.0 => 1.0,
+        v if v > 0.0 && v <= 1.0 => v,
+        _ => {
+            return Err(EngineError::InvalidParameter {
+                field: "watermark.opacity",
+                reason: "must be between 0.0 and 1.0",
+            })
+        }
+    };
+    if !(0.0..=1.0).contains(&op.scale) {
+        return Err(EngineError::InvalidParameter {
+            field: "watermark.scale",
+            reason: "must be between 0.0 and 1.0",
+        });
+    }
+    let placement =
+        watermark::Placement::from_i32(op.placement).ok_or(EngineError::InvalidEnumValue {
+            field: "watermark.placement",
+            value: op.placement,
+        })?;
+    let gravity = Gravity::from_i32(op.gravity).ok_or(EngineError::InvalidEnumValue {
+        field: "watermark.gravity",
+        value: op.gravity,
+    })?;
+
+    let (width, height) = (image.get_width(), image.get_height());
+    let mark = prepare(mark, width, op.scale, opacity, limits)?;
+    let (w, h) = (mark.get_width(), mark.get_height());
+    match placement {
+        watermark::Placement::Absolute => multiple::watermark(image, &mark, op.x, op.y),
+        watermark::Placement::Anchored => {
+            let (free_w, free_h) = (width.saturating_sub(w), height.saturating_sub(h));
+            let (x, y) = gravity.offset(free_w, free_h);
+            let x = inset(x, free_w, op.x);
+            let y = inset(y, free_h, op.y);
+            multiple::watermark(image, &mark, x, y);
+        }
+        watermark::Placement::Tiled => {
+            // 间距来自 spec，不能相信它不会溢出；水印至少 1 个像素，步长不会为 0
+            let (step_x, step_y) = (w.saturating_add(op.x), h.saturating_add(op.y));
+            let tiles = tile(&mark, width, height, step_x, step_y);
+            multiple::watermark(image, &tiles, 0, 0);
+        }
+    }
+    Ok(())
+}
+
+// 按比例缩放水印，并把不透明度乘到 alpha 上
+fn prepare(
+    mark: &PhotonImage,
+    width: u32,
+    scale: f32,
+    opacity: f32,
+    limits: DecodeLimits,
+) -> Result<PhotonImage, EngineError> {
+    let (mut w, mut h) = (mark.get_width(), mark.get_height());
+    let mut pixels = if scale > 0.0 {
+        let scaled_w = ((width as f32 * scale).round() as u32).max(1);
+        h = ((h as f64 * scaled_w as f64 / w as f64).round() as u32).max(1);
+        w = scaled_w;
+        // 很窄很高的水印按宽度放大之后，高度可能远远超过图片本身
+        limits.check_size(w, h)?;
+        transform::resize(mark, w, h, transform::SamplingFilter::Triangle).get_raw_pixels()
+    } else {
+        mark.get_raw_pixels()
+    };
+    if opacity < 1.0 {
+        for p in pixels.chunks_exact_mut(4) {
+            p[3] = (p[3] as f32 * opacity).round() as u8;
+        }
+    }
+    Ok(PhotonImage::new(pixels, w, h))
+}
+
+// 贴着边缘的位置往里移动 margin，居中的位置不变
+fn inset(offset: u32, free: u32, margin: u32) -> u32 {
+    if offset == 0 {
+        margin.min(free)
+    } else if offset == free {
+        free.saturating_sub(margin)
+    } else {
+        offset
+    }
+}
+
+// 生成和图片一样大的透明图层，每隔 step 放一个水印，只需要叠加一次
+fn tile(mark: &PhotonImage, width: u32, height: u32, step_x: u32, step_y: u32) -> PhotonImage {
+    let (w, h) = (mark.get_width() as usize, mark.get_height() as usize);
+    let pixels = mark.get_raw_pixels();
+    let mut layer = vec![0; width as usize * height as usize * 4];
+    for y0 in (0..height as usize).step_by(step_y as usize) {
+        for x0 in (0..width as usize).step_by(step_x as usize) {
+            let copy_w = w.min(width as usize - x0);
+            for row in 0..h.min(height as usize - y0) {
+                let src = row * w * 4;
+                let dst = ((y0 + row) * width as usize + x0) * 4;
+                layer[dst..dst + copy_w * 4].copy_from_slice(&pixels[src..src + copy_w * 4]);
+            }
+        }
+    }
+    PhotonImage::new(layer, width, height)
+}
+
+// 找到图片中有内容（红色通道不为 0）的区域，返回 (x1, y1, x2, y2)
+fn opaque_bounds(image: &PhotonImage) -> Option<(u32, u32, u32, u32)> {
+    let width = image.get_width();
+    let pixels = image.get_raw_pixels();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (i, p) in pixels.chunks_exact(4).enumerate() {
+        if p[0] == 0 {
+            continue;
+        }
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        bounds = Some(match bounds {
+            None => (x, y, x + 1, y + 1),
+            Some((x1, y1, x2, y2)) => (x1.min(x), y1.min(y), x2.max(x + 1), y2.max(y + 1)),
+        });
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, pixel: [u8; 4]) -> PhotonImage {
+        PhotonImage::new(pixel.repeat((width * height) as usize), width, height)
+    }
+
+    fn pixel(image: &PhotonImage, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * image.get_width() + x) * 4) as usize;
+        image.get_raw_pixels()[i..i + 4].try_into().unwrap()
+    }
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    #[test]
+    fn anchored_watermark_should_respect_gravity_and_margin() {
+        let mut image = solid(20, 20, BLACK);
+        let op = Watermark {
+            x: 2,
+            y: 2,
+            placement: watermark::Placement::Anchored as i32,
+            gravity: Gravity::SouthEast as i32,
+            ..Default::default()
+        };
+        stamp(&mut image, &solid(4, 4, RED), &op, DecodeLimits::default()).unwrap();
+        assert_eq!(pixel(&image, 17, 17), RED);
+        assert_eq!(pixel(&image, 14, 14), RED);
+        assert_eq!(pixel(&image, 18, 18), BLACK);
+        assert_eq!(pixel(&image, 13, 13), BLACK);
+    }
+
+    #[test]
+    fn tiled_watermark_should_repeat() {
+        let mut image = solid(20, 20, BLACK);
+        let op = Watermark {
+            x: 6,
+            y: 6,
+            placement: watermark::Placement::Tiled as i32,
+            ..Default::default()
+        };
+        stamp(&mut image, &solid(4, 4, RED), &op, DecodeLimits::default()).unwrap();
+        for (x, y) in [(0, 0), (10, 0), (0, 10), (10, 10)] {
+            assert_eq!(pixel(&image, x, y), RED);
+        }
+        assert_eq!(pixel(&image, 5, 5), BLACK);
+
+        // 很大的间距只放一个水印
+        let op = Watermark {
+            x: u32::MAX,
+            y: u32::MAX,
+            placement: watermark::Placement::Tiled as i32,
+            ..Default::default()
+        };
+        let mut image = solid(20, 20, BLACK);
+        stamp(&mut image, &solid(4, 4, RED), &op, DecodeLimits::default()).unwrap();
+        assert_eq!(pixel(&image, 0, 0), RED);
+        assert_eq!(pixel(&image, 10, 10), BLACK);
+    }
+
+    #[test]
+    fn scale_and_opacity_should_be_applied() {
+        let limits = DecodeLimits::default();
+        let mark = prepare(&solid(10, 5, RED), 40, 0.5, 0.5, limits).unwrap();
+        assert_eq!((mark.get_width(), mark.get_height()), (20, 10));
+        assert_eq!(mark.get_raw_pixels()[3], 128);
+
+        let mut image = solid(20, 20, BLACK);
+        let op = Watermark {
+            opacity: 1.5,
+            ..Default::default()
+        };
+        assert!(matches!(
+            stamp(&mut image, &solid(4, 4, RED), &op, DecodeLimits::default()),
+            Err(EngineError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn text_should_be_rendered_with_color() {
+        let text = watermark::Text {
+            content: "thumbor".to_owned(),
+            size: 30,
+            color: 0xff0000ff,
+        };
+        let mark = render_text(&text, DecodeLimits::default()).unwrap();
+        assert!(mark.get_height() <= 60);
+        let pixels = mark.get_raw_pixels();
+        assert!(pixels.chunks(4).any(|p| p[3] > 0));
+        assert!(pixels.chunks(4).all(|p| p[0..3] == [255, 0, 0]));
+
+        let empty = watermark::Text::default();
+        assert!(render_text(&empty, DecodeLimits::default()).is_err());
+    }
+
+    #[test]
+    fn oversized_watermark_should_fail() {
+        let limits = DecodeLimits {
+            max_width: 100,
+            max_height: 100,
+            max_pixels: 5000,
+        };
+        // 1x100 的水印放大到 40 像素宽之后高度是 4000
+        assert!(matches!(
+            prepare(&solid(1, 100, RED), 40, 1.0, 1.0, limits),
+            Err(EngineError::ImageTooLarge { .. })
+        ));
+
+        let text = watermark::Text {
+            content: "thumbor".to_owned(),
+            size: 512,
+            color: 0,
+        };
+        assert!(matches!(
+            render_text(&text, limits),
+            Err(EngineError::ImageTooLarge { .. })
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+use axum::http::{header::ACCEPT, HeaderMap};
+
+// 没有指定格式时 JPEG 使用的压缩质量
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+// 服务器支持输出的图片格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Png,
+    Jpeg(u8),
+    Gif,
+    Bmp,
+    WebP,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Jpeg(DEFAULT_JPEG_QUALITY)
+    }
+}
+
+impl OutputFormat {
+    // 对应的 Content-Type
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg(_) => "image/jpeg",
+            OutputFormat::Gif => "image/gif",
+            OutputFormat::Bmp => "image/bmp",
+            OutputFormat::WebP => "image/webp",
+        }
+    }
+
+    fn from_mime(mime: &str, quality: u8) -> Option<Self> {
+        match mime {
+            "image/png" => Some(OutputFormat::Png),
+            "image/jpeg" | "image/jpg" => Some(OutputFormat::Jpeg(quality)),
+            "image/gif" => Some(OutputFormat::Gif),
+            "image/bmp" | "image/x-ms-bmp" => Some(OutputFormat::Bmp),
+            "image/webp" => Some(OutputFormat::WebP),
+            _ => None,
+        }
+    }
+
+    // 根据请求的 Accept 头做 content negotiation：在服务器支持的格式里挑 q 值最高的，
+    // q 值相同时以客户端列出的顺序为准；quality 是选中 JPEG 时使用的压缩质量
+    pub fn negotiate(headers: &HeaderMap, quality: u8) -> Self {
+        let fallback = OutputFormat::Jpeg(quality);
+        let accept = match headers.get(ACCEPT).and_then(|v| v.to_str().ok()) {
+            Some(v) => v,
+            None => return fallback,
+        };
+
+        let items: Vec<(String, f32)> = accept
+            .split(',')
+            .map(|item| {
+                let mut params = item.split(';');
+                let mime = params
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase();
+                let q = params
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (mime, q)
+            })
+            .collect();
+        // q=0 表示客户端明确不接受这种格式，通配符也不能选中它
+        let excluded: Vec<_> = items
+            .iter()
+            .filter(|(_, q)| *q <= 0.0)
+            .filter_map(|(mime, _)| Self::from_mime(mime, quality))
+            .collect();
+        // 客户端接受任意图片时，按默认格式优先的顺序选第一个没有被排除的格式
+        let wildcard = [
+            fallback,
+            OutputFormat::Png,
+            OutputFormat::WebP,
+            OutputFormat::Gif,
+            OutputFormat::Bmp,
+        ]
+        .iter()
+        .copied()
+        .find(|format| !excluded.contains(format));
+
+        let mut best: Option<(OutputFormat, f32)> = None;
+        for (mime, q) in items {
+            if q <= 0.0 {
+                continue;
+            }
+            let format = match mime.as_str() {
+                "image/*" | "*/*" => wildcard,
+                mime => Self::from_mime(mime, quality),
+            };
+            if let Some(format) = format {
+                let better = match best {
+                    Some((_, best_q)) => q > best_q,
+                    None => true,
+                };
+                if better {
+                    best = Some((format, q));
+                }
+            }
+        }
+
+        best.map(|(format, _)| format)
+            .or(wildcard)
+            .unwrap_or(fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn missing_accept_should_fallback_to_jpeg() {
+        assert_eq!(
+            OutputFormat::negotiate(&HeaderMap::new(), DEFAULT_JPEG_QUALITY),
+            OutputFormat::default()
+        );
+        assert_eq!(
+            OutputFormat::negotiate(&accept("*/*"), DEFAULT_JPEG_QUALITY),
+            OutputFormat::default()
+        );
+        assert_eq!(
+            OutputFormat::negotiate(&accept("text/html"), DEFAULT_JPEG_QUALITY),
+            OutputFormat::default()
+        );
+    }
+
+    #[test]
+    fn negotiate_should_respect_q_values() {
+        let headers = accept("image/jpeg;q=0.5, image/png;q=0.9, image/webp;q=0.8");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::Png
+        );
+
+        let headers = accept("image/png;q=0, image/gif");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::Gif
+        );
+    }
+
+    #[test]
+    fn wildcard_should_skip_excluded_formats() {
+        let headers = accept("image/jpeg;q=0, */*");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::Png
+        );
+
+        let headers = accept("image/jpg;q=0, image/png;q=0, image/*;q=0.5");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::WebP
+        );
+
+        // 没有可以接受的格式时，同样不返回被排除的格式
+        let headers = accept("image/jpeg;q=0, text/html");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::Png
+        );
+    }
+
+    #[test]
+    fn negotiate_should_work_with_browser_accept() {
+        let headers = accept("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, DEFAULT_JPEG_QUALITY),
+            OutputFormat::WebP
+        );
+    }
+
+    #[test]
+    fn negotiated_jpeg_should_use_given_quality() {
+        let headers = accept("image/jpeg");
+        assert_eq!(
+            OutputFormat::negotiate(&headers, 60),
+            OutputFormat::Jpeg(60)
+        );
+        assert_eq!(
+            OutputFormat::negotiate(&HeaderMap::new(), 60),
+            OutputFormat::Jpeg(60)
+        );
+    }
+}
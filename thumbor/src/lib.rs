@@ -0,0 +1,632 @@
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{Extension, FromRequest, Multipart, Path, Query, RequestParts},
+    handler::{get, post},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::Json,
+    routing::BoxRoute,
+    BoxError, Router,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tower::{load_shed::error::Overloaded, timeout::error::Elapsed, ServiceBuilder};
+use tower_http::{
+    add_extension::AddExtensionLayer, compression::CompressionLayer, trace::TraceLayer,
+};
+use tracing::{info, instrument, warn};
+
+mod cache;
+pub mod config;
+mod engine;
+mod format;
+pub mod pb;
+pub mod signing;
+pub mod source;
+
+pub use config::{Args, Config, ConfigError};
+pub use engine::{
+    DecodeLimits, Engine, EngineError, ImageInfo, PaletteColor, Photon, SpecTransform, Watermarks,
+};
+pub use format::OutputFormat;
+
+use cache::{CacheKey, DiskCache, MemoryCache, SingleFlight, TierStats, TieredCache};
+use config::ImageConfig;
+use pb::*;
+use signing::Signer;
+use source::{FetchError, FileSource, HttpSource, S3Source, Sources};
+
+// 参数使用 serde 做 Deserialize，axum 会自动识别并解析
+#[derive(Deserialize)]
+struct Params {
+    spec: String,
+    url: String,
+}
+
+// 上传图片处理时的 query 参数
+#[derive(Deserialize)]
+struct ProcessParams {
+    spec: Option<String>,
+}
+
+// 带签名的请求参数
+#[derive(Deserialize)]
+struct SignedParams {
+    signature: String,
+    spec: String,
+    url: String,
+}
+
+// 带签名的 /info 请求参数
+#[derive(Deserialize)]
+struct SignedInfoParams {
+    signature: String,
+    url: String,
+}
+
+// 各级缓存的统计数据
+#[derive(Serialize)]
+struct Stats {
+    source: TierStats,
+    thumbnail: TierStats,
+}
+
+// 两级缓存：下载的原图，以及处理并编码后的缩略图
+struct Caches {
+    source: TieredCache,
+    thumbnail: TieredCache,
+    // 正在下载中的原图，同一个 url 同时只会下载一次
+    fetches: SingleFlight<CacheKey, (Bytes, Option<Duration>), FetchError>,
+}
+
+type Cache = Arc<Caches>;
+
+// 配置了签名 key 时，只接受带签名的请求
+type UrlSigner = Option<Arc<Signer>>;
+
+type Source = Arc<Sources>;
+
+type Settings = Arc<Config>;
+
+// 配置里注册的水印，启动时解码好
+type WatermarkRegistry = Arc<Watermarks>;
+
+// multipart 里 JSON 格式的 spec 最大的字节数
+const MAX_SPEC_BYTES: u64 = 64 * 1024;
+
+// 一个 spec 里最多可以通过 url 指定的水印数，每个水印都要额外下载一次
+const MAX_WATERMARK_URLS: usize = 4;
+// 一个 spec 里最多的处理步骤数，超时之后处理图片的线程不会被中断
+const MAX_SPECS: usize = 32;
+
+// 根据配置构建完整的服务，可以直接交给 axum::Server，也可以嵌入到其他服务里
+// 调用方可能直接构造 Config 而没有经过 Config::load，这里再检查一次
+pub fn build_router(config: Config) -> anyhow::Result<Router<BoxRoute>> {
+    config.validate()?;
+    let config: Settings = Arc::new(config);
+    let cache_config = &config.cache;
+    let open_disk = |name: &str, max_bytes: u64| {
+        cache_config
+            .disk_dir
+            .as_ref()
+            .map(|dir| {
+                let dir = dir.join(name);
+                info!("Using disk cache {:?}", dir);
+                DiskCache::open(dir, max_bytes)
+            })
+            .transpose()
+    };
+    let cache: Cache = Arc::new(Caches {
+        source: TieredCache::new(
+            MemoryCache::new(cache_config.source_memory_bytes),
+            open_disk("source", cache_config.source_disk_bytes)?,
+        ),
+        thumbnail: TieredCache::new(
+            MemoryCache::new(cache_config.thumbnail_memory_bytes),
+            open_disk("thumbnail", cache_config.thumbnail_disk_bytes)?,
+        ),
+        fetches: SingleFlight::default(),
+    });
+    let signer: UrlSigner = config
+        .server
+        .signing_key
+        .as_ref()
+        .map(|key| Arc::new(Signer::new(key.as_str())));
+    let source: Source = Arc::new(build_sources(&config)?);
+    let watermarks: WatermarkRegistry = Arc::new(load_watermarks(&config)?);
+    // 构建路由
+    let app = Router::new()
+        // `GET /` 会执行
+        .route("/image/:spec/:url", get(generate))
+        .route("/image/:signature/:spec/:url", get(generate_signed))
+        .route("/process", post(process_upload))
+        .route("/info/:url", get(image_info))
+        .route("/info/:signature/:url", get(image_info_signed))
+        .route("/stats", get(stats))
+        .layer(
+            ServiceBuilder::new()
+                .load_shed()
+                .concurrency_limit(config.server.concurrency_limit)
+                .timeout(Duration::from_secs(config.server.request_timeout))
+                .layer(TraceLayer::new_for_http())
+                .layer(AddExtensionLayer::new(cache))
+                .layer(AddExtensionLayer::new(signer))
+                .layer(AddExtensionLayer::new(source))
+                .layer(AddExtensionLayer::new(config.clone()))
+                .layer(AddExtensionLayer::new(watermarks))
+                .layer(CompressionLayer::new())
+                .into_inner(),
+        )
+        // 中间件的错误转换成对应的状态码
+        .handle_error(|e: BoxError| {
+            // 超时是服务器没有及时处理完，不是客户端发送请求太慢，所以不用 408
+            let status = if e.is::<Elapsed>() {
+                StatusCode::GATEWAY_TIMEOUT
+            } else if e.is::<Overloaded>() {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok::<_, Infallible>(status)
+        })
+        .boxed();
+    Ok(app)
+}
+
+// 根据配置注册可用的原图来源，http(s) 总是可用
+fn build_sources(config: &Config) -> std::io::Result<Sources> {
+    let source = &config.source;
+    let limits = source.fetch_limits();
+    let (default_ttl, max_ttl) = (config.cache.default_ttl(), config.cache.max_ttl());
+    let mut sources = Sources::default();
+
+    let http = Arc::new(HttpSource::new(
+        source.guard(),
+        limits,
+        default_ttl,
+        max_ttl,
+    ));
+    sources.register("http", http.clone());
+    sources.register("https", http);
+
+    if let Some(root) = &source.file_root {
+        info!("Serving file sources under {:?}", root);
+        let file = FileSource::new(root, limits.max_bytes, default_ttl)?;
+        sources.register("file", Arc::new(file));
+    }
+
+    if let Some(s3) = &source.s3 {
+        info!("Serving s3 sources from {}", s3.endpoint);
+        let s3 = S3Source::new(s3.to_s3_config(), limits, default_ttl, max_ttl);
+        sources.register("s3", Arc::new(s3));
+    }
+    Ok(sources)
+}
+
+// 加载配置里注册的水印，文件不存在或者无法解码时拒绝启动
+fn load_watermarks(config: &Config) -> anyhow::Result<Watermarks> {
+    let mut watermarks = Watermarks::default();
+    for (name, path) in &config.watermarks {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read watermark `{}` from {:?}", name, path))?;
+        watermarks
+            .insert(name.as_str(), &data, config.source.decode_limits())
+            .with_context(|| format!("invalid watermark `{}`", name))?;
+    }
+    if !watermarks.is_empty() {
+        info!("Loaded {} watermarks", watermarks.len());
+    }
+    Ok(watermarks)
+}
+
+// 不带签名的请求，只有没有开启签名时才允许
+async fn generate(
+    Path(Params { spec, url }): Path<Params>,
+    Extension(cache): Extension<Cache>,
+    Extension(signer): Extension<UrlSigner>,
+    Extension(source): Extension<Source>,
+    Extension(config): Extension<Settings>,
+    Extension(watermarks): Extension<WatermarkRegistry>,
+    req_headers: HeaderMap,
+) -> Result<(HeaderMap, Bytes), StatusCode> {
+    if signer.is_some() {
+        warn!("Rejected unsigned request");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let spec = percent_decode_str(&spec).decode_utf8_lossy();
+    let url = percent_decode_str(&url).decode_utf8_lossy();
+    render(
+        &spec,
+        &url,
+        cache,
+        source,
+        &config,
+        watermarks,
+        &req_headers,
+    )
+    .await
+}
+
+// 带签名的请求，在下载原图之前先校验签名；没有开启签名时不做校验
+async fn generate_signed(
+    Path(SignedParams {
+        signature,
+        spec,
+        url,
+    }): Path<SignedParams>,
+    Extension(cache): Extension<Cache>,
+    Extension(signer): Extension<UrlSigner>,
+    Extension(source): Extension<Source>,
+    Extension(config): Extension<Settings>,
+    Extension(watermarks): Extension<WatermarkRegistry>,
+    req_headers: HeaderMap,
+) -> Result<(HeaderMap, Bytes), StatusCode> {
+    // 签名针对的是 percent decode 之后的 spec 和 url，和 render 实际使用的内容一致
+    let spec = percent_decode_str(&spec).decode_utf8_lossy();
+    let url = percent_decode_str(&url).decode_utf8_lossy();
+    if let Some(signer) = signer {
+        if !signer.verify(&signature, &spec, &url) {
+            warn!("Rejected request with invalid signature");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+    render(
+        &spec,
+        &url,
+        cache,
+        source,
+        &config,
+        watermarks,
+        &req_headers,
+    )
+    .await
+}
+
+// spec 和 url 都已经 percent decode
+async fn render(
+    spec: &str,
+    url: &str,
+    cache: Cache,
+    source: Source,
+    config: &Config,
+    watermarks: WatermarkRegistry,
+    req_headers: &HeaderMap,
+) -> Result<(HeaderMap, Bytes), StatusCode> {
+    let image_spec = ImageSpec::parse_with_presets(spec, &config.presets).map_err(|e| {
+        warn!("Invalid spec {}: {}", spec, e);
+        StatusCode::BAD_REQUEST
+    })?;
+    check_spec(&image_spec)?;
+
+    // 根据 Accept 头决定输出的图片格式，spec 里指定了格式时以 spec 为准
+    let quality = config.image.default_quality;
+    let format = image_spec
+        .output_format(quality)
+        .unwrap_or_else(|| OutputFormat::negotiate(req_headers, quality));
+
+    let key = CacheKey::thumbnail(url, &image_spec, format);
+    let image = match cache.thumbnail.get(&key).await {
+        Some((image, _)) => {
+            info!("Match thumbnail cache {}", key);
+            image
+        }
+        None => {
+            let (data, source_ttl) = retrieve_image(url, cache.clone(), source.clone()).await?;
+            let image = transform(
+                data,
+                image_spec,
+                format,
+                cache.clone(),
+                source,
+                config,
+                watermarks,
+            )
+            .await?;
+            // 缩略图不能比原图缓存得更久，上游不允许缓存原图时也不缓存缩略图
+            if let Some(ttl) = source_ttl {
+                let ttl = ttl.min(config.cache.default_ttl());
+                cache.thumbnail.put(key, image.clone(), ttl).await;
+            }
+            image
+        }
+    };
+
+    Ok((image_headers(format), image))
+}
+
+// 直接上传图片处理，不需要原图的 url：
+// - multipart/form-data：image 字段是图片，spec 字段是 JSON 格式的 ImageSpec
+// - 其他：请求体就是图片，JSON 格式的 ImageSpec 放在 query 的 spec 参数里
+// 上传的请求没有办法签名，开启签名时不可用
+async fn process_upload(
+    Query(params): Query<ProcessParams>,
+    Extension(cache): Extension<Cache>,
+    Extension(signer): Extension<UrlSigner>,
+    Extension(source): Extension<Source>,
+    Extension(config): Extension<Settings>,
+    Extension(watermarks): Extension<WatermarkRegistry>,
+    req: Request<Body>,
+) -> Result<(HeaderMap, Bytes), StatusCode> {
+    // 在读取请求体之前拒绝
+    if signer.is_some() {
+        warn!("Rejected unsigned request");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let req_headers = req.headers().clone();
+    let max_bytes = config.source.max_bytes;
+    let is_multipart = req_headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.starts_with("multipart/form-data"));
+
+    let mut spec = params.spec.map(Bytes::from);
+    let data = if is_multipart {
+        let mut multipart = Multipart::from_request(&mut RequestParts::new(req))
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let mut data = None;
+        while let Some(field) = multipart.next_field().await.map_err(|e| {
+            warn!("Invalid multipart body: {}", e);
+            StatusCode::BAD_REQUEST
+        })? {
+            let name = field.name().map(str::to_owned);
+            match name.as_deref() {
+                Some("image") => data = Some(read_limited(field, max_bytes).await?),
+                Some("spec") => spec = Some(read_limited(field, MAX_SPEC_BYTES).await?),
+                _ => {}
+            }
+        }
+        data.ok_or(StatusCode::BAD_REQUEST)?
+    } else {
+        read_limited(req.into_body(), max_bytes).await?
+    };
+
+    let spec = spec.ok_or(StatusCode::BAD_REQUEST)?;
+    let image_spec: ImageSpec = serde_json::from_slice(&spec).map_err(|e| {
+        warn!("Invalid JSON spec: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    check_spec(&image_spec)?;
+    let quality = config.image.default_quality;
+    let format = image_spec
+        .output_format(quality)
+        .unwrap_or_else(|| OutputFormat::negotiate(&req_headers, quality));
+
+    let image = transform(data, image_spec, format, cache, source, &config, watermarks).await?;
+    Ok((image_headers(format), image))
+}
+
+// 读取请求体或者 multipart 的一个字段，超过 max_bytes 时返回 413
+async fn read_limited<S, E>(stream: S, max_bytes: u64) -> Result<Bytes, StatusCode>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    futures::pin_mut!(stream);
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            warn!("Failed to read request body: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        if (buf.len() + chunk.len()) as u64 > max_bytes {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+// 在下载任何图片之前限制 spec 的处理量
+fn check_spec(image_spec: &ImageSpec) -> Result<(), StatusCode> {
+    if image_spec.specs.len() > MAX_SPECS {
+        warn!("Too many specs: {}", image_spec.specs.len());
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mark_urls = image_spec.watermark_urls().len();
+    if mark_urls > MAX_WATERMARK_URLS {
+        warn!("Too many watermark urls: {}", mark_urls);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+// 下载 spec 里通过 url 指定的水印，然后处理图片
+async fn transform(
+    data: Bytes,
+    image_spec: ImageSpec,
+    format: OutputFormat,
+    cache: Cache,
+    source: Source,
+    config: &Config,
+    watermarks: WatermarkRegistry,
+) -> Result<Bytes, StatusCode> {
+    // 通过 url 指定的水印和原图一样下载（并缓存）
+    let mut marks = Vec::new();
+    for mark_url in image_spec.watermark_urls() {
+        let (mark, _) = retrieve_image(mark_url, cache.clone(), source.clone()).await?;
+        marks.push((mark_url.to_owned(), mark));
+    }
+
+    // 图片处理很耗 CPU，放到专门的线程池里做，不阻塞其他请求
+    let limits = config.source.decode_limits();
+    let options = config.image.clone();
+    let image = tokio::task::spawn_blocking(move || {
+        let assets = Assets { watermarks, marks };
+        process(data, &image_spec, format, limits, &options, assets)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+
+    info!("Finished processing: image size {}", image.len());
+    Ok(Bytes::from(image))
+}
+
+// 返回图片时的响应头
+fn image_headers(format: OutputFormat) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.insert(
+        "content-type",
+        HeaderValue::from_static(format.content_type()),
+    );
+    // 同一个 url 会因为 Accept 不同返回不同的内容，需要告诉缓存
+    headers.insert("vary", HeaderValue::from_static("accept"));
+    headers
+}
+
+// 处理图片时除了原图之外需要用到的资源
+struct Assets {
+    watermarks: WatermarkRegistry,
+    // 通过 url 指定的水印，还没有解码
+    marks: Vec<(String, Bytes)>,
+}
+
+// 使用 image engine 处理
+fn process(
+    data: Bytes,
+    spec: &ImageSpec,
+    format: OutputFormat,
+    limits: DecodeLimits,
+    options: &ImageConfig,
+    assets: Assets,
+) -> Result<Vec<u8>, StatusCode> {
+    // 解码之前先检查尺寸
+    limits.check(&data)?;
+    let mut marks = Watermarks::default();
+    for (url, mark) in assets.marks {
+        marks.insert(url, &mark, limits)?;
+    }
+    // 图片头是完整的，但是数据损坏或者被截断了，属于客户端或者上游的问题
+    let engine: Photon = data
+        .try_into()
+        .map_err(|e: anyhow::Error| EngineError::Decode(e.to_string()))?;
+    let mut engine = engine
+        .with_default_quality(options.default_quality)
+        .with_metadata(!options.strip_metadata)
+        .with_watermarks(assets.watermarks, marks)
+        .with_limits(limits);
+    engine.apply(&spec.specs)?;
+    let (image, _) = engine.generate(format)?;
+    Ok(image)
+}
+
+// 把 engine 的错误映射成对应的 http 状态码
+impl From<EngineError> for StatusCode {
+    fn from(e: EngineError) -> Self {
+        warn!("Failed to process image: {}", e);
+        match e {
+            EngineError::InvalidEnumValue { .. }
+            | EngineError::InvalidParameter { .. }
+            | EngineError::Decode(_) => StatusCode::BAD_REQUEST,
+            EngineError::ImageTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            EngineError::CropOutOfBounds { .. } | EngineError::ZeroSizeResize { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            EngineError::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// 不允许访问的来源返回 403，下载失败返回 400，原图太大返回 413，超时返回 504
+impl From<FetchError> for StatusCode {
+    fn from(e: FetchError) -> Self {
+        warn!("Failed to retrieve image: {}", e);
+        match e {
+            FetchError::Forbidden(_) => StatusCode::FORBIDDEN,
+            FetchError::Upstream(_) => StatusCode::BAD_REQUEST,
+            FetchError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            FetchError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+// 不带签名的 /info 请求，只有没有开启签名时才允许
+async fn image_info(
+    Path(url): Path<String>,
+    Extension(cache): Extension<Cache>,
+    Extension(signer): Extension<UrlSigner>,
+    Extension(source): Extension<Source>,
+    Extension(config): Extension<Settings>,
+) -> Result<Json<ImageInfo>, StatusCode> {
+    if signer.is_some() {
+        warn!("Rejected unsigned request");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let url = percent_decode_str(&url).decode_utf8_lossy();
+    inspect(&url, cache, source, &config).await
+}
+
+// 带签名的 /info 请求，和生成缩略图一样在下载原图之前校验签名
+async fn image_info_signed(
+    Path(SignedInfoParams { signature, url }): Path<SignedInfoParams>,
+    Extension(cache): Extension<Cache>,
+    Extension(signer): Extension<UrlSigner>,
+    Extension(source): Extension<Source>,
+    Extension(config): Extension<Settings>,
+) -> Result<Json<ImageInfo>, StatusCode> {
+    let url = percent_decode_str(&url).decode_utf8_lossy();
+    if let Some(signer) = signer {
+        if !signer.verify_info(&signature, &url) {
+            warn!("Rejected request with invalid signature");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+    inspect(&url, cache, source, &config).await
+}
+
+// 原图的尺寸、格式、主色等信息，和生成缩略图共用原图的缓存
+async fn inspect(
+    url: &str,
+    cache: Cache,
+    source: Source,
+    config: &Config,
+) -> Result<Json<ImageInfo>, StatusCode> {
+    let (data, _) = retrieve_image(url, cache, source).await?;
+    let limits = config.source.decode_limits();
+    let info = tokio::task::spawn_blocking(move || ImageInfo::inspect(&data, limits))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+    Ok(Json(info))
+}
+
+// 返回缓存的命中率等统计数据，用于监控
+async fn stats(Extension(cache): Extension<Cache>) -> Json<Stats> {
+    let source = cache.source.stats();
+    let thumbnail = cache.thumbnail.stats();
+    Json(Stats { source, thumbnail })
+}
+
+// 返回原图以及它还可以缓存多久，上游不允许缓存时为 None
+#[instrument(level = "info", skip(cache, source))]
+async fn retrieve_image(
+    url: &str,
+    cache: Cache,
+    source: Source,
+) -> Result<(Bytes, Option<Duration>), FetchError> {
+    let key = CacheKey::source(url);
+
+    if let Some((data, ttl)) = cache.source.get(&key).await {
+        info!("Match cache {}", key);
+        return Ok((data, Some(ttl)));
+    }
+
+    // 同一个 url 的并发请求合并成一次下载，不同 url 之间并行下载
+    let url = url.to_owned();
+    let c = cache.clone();
+    cache
+        .fetches
+        .run(key, move || async move {
+            info!("Retrieve url");
+            let (data, ttl) = source.fetch(&url).await?;
+            // 上游不允许缓存时 ttl 为 None
+            if let Some(ttl) = ttl {
+                c.source.put(key, data.clone(), ttl).await;
+            }
+            Ok((data, ttl))
+        })
+        .await
+}
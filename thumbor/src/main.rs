@@ -1,66 +1,38 @@
-use anyhow::Result;
-use axum::{
-    extract::{Extension, Path},
-    handler::get,
-    http::{HeaderMap, HeaderValue, StatusCode},
-    Router,
-};
-use bytes::Bytes;
-use image::ImageOutputFormat;
-use lru::LruCache;
-use percent_encoding::{percent_decode_str, percent_encode, NON_ALPHANUMERIC};
-use serde::Deserialize;
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-    sync::Arc,
-    time::Duration,
-};
-use tokio::sync::Mutex;
-use tower::ServiceBuilder;
-use tower_http::{
-    add_extension::AddExtensionLayer, compression::CompressionLayer, trace::TraceLayer,
-};
-use tracing::{info, instrument};
-
-mod engine;
-mod pb;
-
-use engine::{Engine, Photon};
-use pb::*;
-
-// 参数使用 serde 做 Deserialize，axum 会自动识别并解析
-#[derive(Deserialize)]
-struct Params {
-    spec: String,
-    url: String,
-}
-
-type Cache = Arc<Mutex<LruCache<u64, Bytes>>>;
+use clap::Parser;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use std::net::SocketAddr;
+use thumbor::{build_router, pb::*, signing::Signer, Args, Config};
+use tracing::info;
 
 #[tokio::main]
 async fn main() {
     // 初始化 tracing
     tracing_subscriber::fmt::init();
-    let cache: Cache = Arc::new(Mutex::new(LruCache::new(1024)));
-    // 构建路由
-    let app = Router::new()
-        // `GET /` 会执行
-        .route("/image/:spec/:url", get(generate))
-        .layer(
-            ServiceBuilder::new()
-                .load_shed()
-                .concurrency_limit(1024)
-                .timeout(Duration::from_secs(10))
-                .layer(TraceLayer::new_for_http())
-                .layer(AddExtensionLayer::new(cache))
-                .layer(CompressionLayer::new())
-                .into_inner(),
-        );
+    // 配置有问题时直接退出，不要带着错误的配置运行
+    let config = match Config::load(Args::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let addr = config.server.listen;
+    let signer = config.server.signing_key.as_deref().map(Signer::new);
+    // 水印文件或者缓存目录无法读取时同样直接退出
+    let app = match build_router(config) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("Failed to start server: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
     // 运行 web 服务器
-    let addr = "127.0.0.1:3000".parse().unwrap();
-    print_test_url("https://images.pexels.com/photos/1562477/pexels-photo-1562477.jpeg?auto=compress&cs=tinysrgb&dpr=3&h=750&w=1260");
+    let test_url = "https://images.pexels.com/photos/1562477/pexels-photo-1562477.jpeg?auto=compress&cs=tinysrgb&dpr=3&h=750&w=1260";
+    match signer {
+        Some(signer) => print_signed_test_url(addr, test_url, &signer),
+        None => print_test_url(addr, test_url),
+    }
     info!("Listening on {}", addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
@@ -68,69 +40,24 @@ async fn main() {
         .unwrap();
 }
 
-// basic handler that responds with a static string
-async fn generate(
-    Path(Params { spec, url }): Path<Params>,
-    Extension(cache): Extension<Cache>,
-) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
-    let spec: ImageSpec = spec
-        .as_str()
-        .try_into()
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let url: &str = &percent_decode_str(&url).decode_utf8_lossy();
-    let data = retrieve_image(url, cache)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    // 使用 image engine 处理
-    let mut engine: Photon = data
-        .try_into()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    engine.apply(&spec.specs);
-    // TODO: 这里目前类型写死了，应该使用 content negotiation
-    let image = engine.generate(ImageOutputFormat::Jpeg(85));
-
-    info!("Finished processing: image size {}", image.len());
-
-    let mut headers = HeaderMap::new();
-
-    headers.insert("content-type", HeaderValue::from_static("image/jpeg"));
-    Ok((headers, image))
+// 调试辅助函数
+fn print_test_url(addr: SocketAddr, url: &str) {
+    let s = test_spec();
+    let test_image = percent_encode(url.as_bytes(), NON_ALPHANUMERIC).to_string();
+    println!("test url: http://{}/image/{}/{}", addr, s, test_image);
 }
 
-#[instrument(level = "info", skip(cache))]
-async fn retrieve_image(url: &str, cache: Cache) -> Result<Bytes> {
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
-    let key = hasher.finish();
-
-    let g = &mut cache.lock().await;
-    let data = match g.get(&key) {
-        Some(v) => {
-            info!("Match cache {}", key);
-            v.to_owned()
-        }
-        None => {
-            info!("Retrieve url");
-            let resp = reqwest::get(url).await?;
-            let data = resp.bytes().await?;
-            g.put(key, data.clone());
-            data
-        }
-    };
-
-    Ok(data)
+// 调试辅助函数，开启签名时生成带签名的测试 url
+fn print_signed_test_url(addr: SocketAddr, url: &str, signer: &Signer) {
+    let path = signer.signed_path(&test_spec(), url);
+    println!("signed test url: http://{}{}", addr, path);
 }
 
-// 调试辅助函数
-fn print_test_url(url: &str) {
+fn test_spec() -> String {
     use std::borrow::Borrow;
     let spec1 = Spec::new_resize(500, 800, resize::SampleFilter::CatmullRom);
     let spec2 = Spec::new_watermark(20, 20);
     let spec3 = Spec::new_filter(filter::Filter::Marine);
     let image_spec = ImageSpec::new(vec![spec1, spec2, spec3]);
-    let s: String = image_spec.borrow().into();
-    let test_image = percent_encode(url.as_bytes(), NON_ALPHANUMERIC).to_string();
-    println!("test url: http://localhost:3000/image/{}/{}", s, test_image);
+    image_spec.borrow().into()
 }
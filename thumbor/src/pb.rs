@@ -1,8 +1,11 @@
+use crate::format::OutputFormat;
 use base64::{decode_config, encode_config, URL_SAFE_NO_PAD};
 use photon_rs::transform::SamplingFilter;
 use prost::Message;
+use std::collections::HashMap;
 
 mod abi;
+mod text;
 
 pub use abi::*;
 
@@ -10,6 +13,43 @@ impl ImageSpec {
     pub fn new(specs: Vec<Spec>) -> Self {
         Self { specs }
     }
+
+    // specs 里指定的输出格式，多次指定时以最后一个为准
+    pub fn output_format(&self, default_quality: u8) -> Option<OutputFormat> {
+        self.specs.iter().rev().find_map(|spec| match spec.data {
+            Some(spec::Data::Format(ref v)) => v.to_output_format(default_quality),
+            _ => None,
+        })
+    }
+
+    // 通过 url 指定的水印，需要在处理之前和原图一样下载好，同一个 url 只下载一次
+    pub fn watermark_urls(&self) -> Vec<&str> {
+        let mut urls = Vec::new();
+        for spec in self.specs.iter() {
+            if let Some(spec::Data::Watermark(Watermark {
+                source: Some(watermark::Source::Url(ref url)),
+                ..
+            })) = spec.data
+            {
+                if !urls.contains(&url.as_str()) {
+                    urls.push(url.as_str());
+                }
+            }
+        }
+        urls
+    }
+
+    // 和 TryFrom<&str> 一样，另外可以用 `preset:名字` 引用 presets 里的 spec
+    pub fn parse_with_presets(
+        value: &str,
+        presets: &HashMap<String, String>,
+    ) -> anyhow::Result<Self> {
+        match text::parse_with_presets(value, presets) {
+            Ok(spec) => Ok(spec),
+            Err(e) if value.contains("preset:") => Err(e),
+            Err(_) => value.try_into(),
+        }
+    }
 }
 
 // 让 ImageSpec 可以生成一个字符串
@@ -24,9 +64,18 @@ impl From<&ImageSpec> for String {
 impl TryFrom<&str> for ImageSpec {
     type Error = anyhow::Error;
 
+    // 先按文本形式解析，比如 resize(300,200)|fliph，不是的话再按 base64 编码的 protobuf 解析
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let data = decode_config(value, URL_SAFE_NO_PAD)?;
-        Ok(ImageSpec::decode(&data[..])?)
+        let err = match text::parse(value) {
+            Ok(spec) => return Ok(spec),
+            Err(e) => e,
+        };
+        match decode_config(value, URL_SAFE_NO_PAD) {
+            Ok(data) => Ok(ImageSpec::decode(&data[..])?),
+            // 看起来像文本形式时返回文本解析的错误，更容易看出问题在哪里
+            Err(_) if value.contains('(') => Err(err),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
@@ -38,10 +87,65 @@ impl filter::Filter {
             filter::Filter::Oceanic => Some("oceanic"),
             filter::Filter::Islands => Some("islands"),
             filter::Filter::Marine => Some("marine"),
+            filter::Filter::Seagreen => Some("seagreen"),
+            filter::Filter::Flagblue => Some("flagblue"),
+            filter::Filter::Liquid => Some("liquid"),
+            filter::Filter::Diamante => Some("diamante"),
+            filter::Filter::Radio => Some("radio"),
+            filter::Filter::Twenties => Some("twenties"),
+            filter::Filter::Rosetint => Some("rosetint"),
+            filter::Filter::Mauve => Some("mauve"),
+            filter::Filter::Bluechrome => Some("bluechrome"),
+            filter::Filter::Vintage => Some("vintage"),
+            filter::Filter::Perfume => Some("perfume"),
+            filter::Filter::Serenity => Some("serenity"),
+            filter::Filter::Golden => Some("golden"),
+            filter::Filter::PastelPink => Some("pastel_pink"),
+            filter::Filter::Cali => Some("cali"),
+            filter::Filter::Dramatic => Some("dramatic"),
+            filter::Filter::Firenze => Some("firenze"),
+            filter::Filter::Obsidian => Some("obsidian"),
+            filter::Filter::Lofi => Some("lofi"),
         }
     }
 }
 
+// 把 spec 里指定的输出格式转换成 engine 使用的 OutputFormat，未指定时返回 None
+// quality 为 0 时使用服务配置的默认质量
+impl Format {
+    pub fn to_output_format(&self, default_quality: u8) -> Option<OutputFormat> {
+        let quality = match self.quality {
+            0 => default_quality,
+            q => q.min(100) as u8,
+        };
+        match format::Type::from_i32(self.ftype)? {
+            format::Type::Unspecified => None,
+            format::Type::Png => Some(OutputFormat::Png),
+            format::Type::Jpeg => Some(OutputFormat::Jpeg(quality)),
+            format::Type::Gif => Some(OutputFormat::Gif),
+            format::Type::Bmp => Some(OutputFormat::Bmp),
+            format::Type::Webp => Some(OutputFormat::WebP),
+        }
+    }
+}
+
+impl Gravity {
+    // 在 free_width x free_height 的空余空间中，按照 gravity 计算左上角的偏移
+    pub fn offset(self, free_width: u32, free_height: u32) -> (u32, u32) {
+        let x = match self {
+            Gravity::West | Gravity::NorthWest | Gravity::SouthWest => 0,
+            Gravity::East | Gravity::NorthEast | Gravity::SouthEast => free_width,
+            Gravity::Center | Gravity::North | Gravity::South => free_width / 2,
+        };
+        let y = match self {
+            Gravity::North | Gravity::NorthWest | Gravity::NorthEast => 0,
+            Gravity::South | Gravity::SouthWest | Gravity::SouthEast => free_height,
+            Gravity::Center | Gravity::West | Gravity::East => free_height / 2,
+        };
+        (x, y)
+    }
+}
+
 // 在我们定义的 SampleFilter 和 photon_rs 的 SamplingFilter 间转换
 impl From<resize::SampleFilter> for SamplingFilter {
     fn from(v: resize::SampleFilter) -> Self {
@@ -65,6 +169,7 @@ impl Spec {
                 height,
                 rtype: resize::ResizeType::SeamCarve as i32,
                 filter: resize::SampleFilter::Undefined as i32,
+                ..Default::default()
             })),
         }
     }
@@ -76,6 +181,27 @@ impl Spec {
                 height,
                 rtype: resize::ResizeType::Normal as i32,
                 filter: filter as i32,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_resize_fit(
+        width: u32,
+        height: u32,
+        fit: resize::Fit,
+        gravity: Gravity,
+        filter: resize::SampleFilter,
+    ) -> Self {
+        Self {
+            data: Some(spec::Data::Resize(Resize {
+                width,
+                height,
+                rtype: resize::ResizeType::Normal as i32,
+                filter: filter as i32,
+                fit: fit as i32,
+                gravity: gravity as i32,
+                background: 0,
             })),
         }
     }
@@ -90,7 +216,82 @@ impl Spec {
 
     pub fn new_watermark(x: u32, y: u32) -> Self {
         Self {
-            data: Some(spec::Data::Watermark(Watermark { x, y })),
+            data: Some(spec::Data::Watermark(Watermark {
+                x,
+                y,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_format(ftype: format::Type, quality: u32) -> Self {
+        Self {
+            data: Some(spec::Data::Format(Format {
+                ftype: ftype as i32,
+                quality,
+            })),
+        }
+    }
+
+    pub fn new_brightness(brightness: i32) -> Self {
+        Self {
+            data: Some(spec::Data::Brightness(Brightness { brightness })),
+        }
+    }
+
+    pub fn new_saturation(amount: f32) -> Self {
+        Self {
+            data: Some(spec::Data::Saturation(Saturation { amount })),
+        }
+    }
+
+    pub fn new_hue_rotate(degrees: f32) -> Self {
+        Self {
+            data: Some(spec::Data::HueRotate(HueRotate { degrees })),
+        }
+    }
+
+    pub fn new_blur(radius: i32) -> Self {
+        Self {
+            data: Some(spec::Data::Blur(Blur { radius })),
+        }
+    }
+
+    pub fn new_sharpen() -> Self {
+        Self {
+            data: Some(spec::Data::Sharpen(Sharpen {})),
+        }
+    }
+
+    pub fn new_grayscale() -> Self {
+        Self {
+            data: Some(spec::Data::Grayscale(Grayscale {})),
+        }
+    }
+
+    pub fn new_smart_crop(width: u32, height: u32) -> Self {
+        Self {
+            data: Some(spec::Data::SmartCrop(SmartCrop { width, height })),
+        }
+    }
+
+    pub fn new_focal_crop(width: u32, height: u32, x: f32, y: f32) -> Self {
+        Self {
+            data: Some(spec::Data::FocalCrop(FocalCrop {
+                width,
+                height,
+                x,
+                y,
+            })),
+        }
+    }
+
+    pub fn new_rotate(degrees: f32, background: u32) -> Self {
+        Self {
+            data: Some(spec::Data::Rotate(Rotate {
+                degrees,
+                background,
+            })),
         }
     }
 }
@@ -108,4 +309,104 @@ mod tests {
         let s: String = image_spec.borrow().into();
         assert_eq!(image_spec, s.as_str().try_into().unwrap());
     }
+
+    #[test]
+    fn every_filter_should_map_to_photon_filter() {
+        use photon_rs::{filters, PhotonImage};
+
+        assert_eq!(filter::Filter::Unspecified.to_str(), None);
+        // 各种颜色的渐变，保证每个滤镜都会改变一些像素
+        let pixels = (0..16 * 16)
+            .flat_map(|i: u32| {
+                [
+                    (i % 16 * 16) as u8,
+                    (i / 16 * 16) as u8,
+                    (i * 7 % 256) as u8,
+                    255,
+                ]
+            })
+            .collect();
+        let image = PhotonImage::new(pixels, 16, 16);
+        let apply = |name: &str| {
+            let mut image = image.clone();
+            filters::filter(&mut image, name);
+            image.get_raw_pixels()
+        };
+
+        // photon 不认识的名字不会报错，而是使用默认的效果，需要和它区分开
+        let unknown = apply("no_such_filter");
+        for f in (1..).map_while(filter::Filter::from_i32) {
+            let name = f.to_str().unwrap();
+            let filtered = apply(name);
+            assert_ne!(
+                filtered,
+                image.get_raw_pixels(),
+                "{} should change the image",
+                name
+            );
+            assert_ne!(filtered, unknown, "{} should be known to photon", name);
+        }
+    }
+
+    #[test]
+    fn format_spec_should_convert_to_output_format() {
+        let jpeg = Format {
+            ftype: format::Type::Jpeg as i32,
+            quality: 0,
+        };
+        assert_eq!(jpeg.to_output_format(60), Some(OutputFormat::Jpeg(60)));
+        let jpeg = Format {
+            ftype: format::Type::Jpeg as i32,
+            quality: 250,
+        };
+        assert_eq!(jpeg.to_output_format(60), Some(OutputFormat::Jpeg(100)));
+        assert_eq!(Format::default().to_output_format(60), None);
+    }
+
+    #[test]
+    fn watermark_urls_should_be_deduplicated() {
+        let mark = |url: &str| Spec {
+            data: Some(spec::Data::Watermark(Watermark {
+                source: Some(watermark::Source::Url(url.to_owned())),
+                ..Default::default()
+            })),
+        };
+        let image_spec = ImageSpec::new(vec![
+            mark("https://a.com/1.png"),
+            Spec::new_watermark(0, 0),
+            mark("https://a.com/2.png"),
+            mark("https://a.com/1.png"),
+        ]);
+        assert_eq!(
+            image_spec.watermark_urls(),
+            vec!["https://a.com/1.png", "https://a.com/2.png"]
+        );
+    }
+
+    #[test]
+    fn spec_should_round_trip_through_json() {
+        let image_spec = ImageSpec::new(vec![
+            Spec::new_resize(600, 600, resize::SampleFilter::CatmullRom),
+            Spec::new_filter(filter::Filter::Marine),
+            Spec::new_format(format::Type::Webp, 80),
+        ]);
+        let json = serde_json::to_string(&image_spec).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ImageSpec>(&json).unwrap(),
+            image_spec
+        );
+
+        // 没有写的字段使用默认值
+        let json = r#"{"specs": [{"data": {"resize": {"width": 100, "height": 50}}}, {"data": {"fliph": {}}}]}"#;
+        let parsed: ImageSpec = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            parsed.specs,
+            vec![
+                Spec::new_resize(100, 50, resize::SampleFilter::Undefined),
+                Spec {
+                    data: Some(spec::Data::Fliph(Fliph {})),
+                },
+            ]
+        );
+    }
 }
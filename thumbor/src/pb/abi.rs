@@ -1,11 +1,17 @@
 /// 一个 ImageSpec 是一个有序的数组，服务器按照 spec 的顺序处理
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct ImageSpec {
     #[prost(message, repeated, tag="1")]
     pub specs: ::prost::alloc::vec::Vec<Spec>,
 }
 /// 处理图片改变大小
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Resize {
     #[prost(uint32, tag="1")]
     pub width: u32,
@@ -15,16 +21,28 @@ pub struct Resize {
     pub rtype: i32,
     #[prost(enumeration="resize::SampleFilter", tag="4")]
     pub filter: i32,
+    #[prost(enumeration="resize::Fit", tag="5")]
+    pub fit: i32,
+    /// COVER 时保留哪一部分，CONTAIN 时图片放在哪里
+    #[prost(enumeration="Gravity", tag="6")]
+    pub gravity: i32,
+    /// CONTAIN 时填充的 RGBA 颜色，0 是透明
+    #[prost(fixed32, tag="7")]
+    pub background: u32,
 }
 /// Nested message and enum types in `Resize`.
 pub mod resize {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[repr(i32)]
     pub enum ResizeType {
         Normal = 0,
         SeamCarve = 1,
     }
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[repr(i32)]
     pub enum SampleFilter {
         Undefined = 0,
@@ -34,9 +52,28 @@ pub mod resize {
         Gaussian = 4,
         Lanczos3 = 5,
     }
+    /// width 和 height 都给出时，如何处理和原图比例不一致的情况
+    /// 只给出其中一个（另一个为 0）时，按原图比例自动计算另一个
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[repr(i32)]
+    pub enum Fit {
+        /// 拉伸到指定尺寸，不保持比例
+        Fill = 0,
+        /// 保持比例缩放到能放进指定尺寸，再用 background 填充到指定尺寸
+        Contain = 1,
+        /// 保持比例缩放到刚好覆盖指定尺寸，再按 gravity 裁掉多余的部分
+        Cover = 2,
+        /// 保持比例缩放到能放进指定尺寸，不填充，输出可能比指定尺寸小
+        Inside = 3,
+    }
 }
 /// 处理图片截取
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Crop {
     #[prost(uint32, tag="1")]
     pub x1: u32,
@@ -47,55 +84,270 @@ pub struct Crop {
     #[prost(uint32, tag="4")]
     pub y2: u32,
 }
+/// 自动选择图片中细节最丰富（边缘最多）的区域，截取 width x height
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct SmartCrop {
+    #[prost(uint32, tag="1")]
+    pub width: u32,
+    #[prost(uint32, tag="2")]
+    pub height: u32,
+}
+/// 以焦点为中心截取 width x height，焦点坐标是相对于图片宽高的比例（0.0-1.0）
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct FocalCrop {
+    #[prost(uint32, tag="1")]
+    pub width: u32,
+    #[prost(uint32, tag="2")]
+    pub height: u32,
+    #[prost(float, tag="3")]
+    pub x: f32,
+    #[prost(float, tag="4")]
+    pub y: f32,
+}
 /// 处理水平翻转
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Fliph {
 }
 /// 处理垂直翻转
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Flipv {
 }
 /// 处理对比度
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Contrast {
     #[prost(float, tag="1")]
     pub contrast: f32,
 }
 /// 处理滤镜
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Filter {
     #[prost(enumeration="filter::Filter", tag="1")]
     pub filter: i32,
 }
 /// Nested message and enum types in `Filter`.
 pub mod filter {
+    /// 和 photon_rs::filters::filter 支持的滤镜一一对应
+    /// https://docs.rs/photon-rs/0.3.1/photon_rs/filters/fn.filter.html
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[repr(i32)]
     pub enum Filter {
         Unspecified = 0,
         Oceanic = 1,
         Islands = 2,
-        /// more: https://docs.rs/photon-rs/0.3.1/photon_rs/filters/fn.filter.html
         Marine = 3,
+        Seagreen = 4,
+        Flagblue = 5,
+        Liquid = 6,
+        Diamante = 7,
+        Radio = 8,
+        Twenties = 9,
+        Rosetint = 10,
+        Mauve = 11,
+        Bluechrome = 12,
+        Vintage = 13,
+        Perfume = 14,
+        Serenity = 15,
+        Golden = 16,
+        PastelPink = 17,
+        Cali = 18,
+        Dramatic = 19,
+        Firenze = 20,
+        Obsidian = 21,
+        Lofi = 22,
     }
 }
 /// 处理水印
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Watermark {
     #[prost(uint32, tag="1")]
     pub x: u32,
     #[prost(uint32, tag="2")]
     pub y: u32,
+    /// 不透明度（0.0-1.0），0 和 1.0 一样表示完全不透明
+    #[prost(float, tag="6")]
+    pub opacity: f32,
+    /// 水印宽度占图片宽度的比例（0.0-1.0），0 表示保持水印本身的大小
+    #[prost(float, tag="7")]
+    pub scale: f32,
+    #[prost(enumeration="watermark::Placement", tag="8")]
+    pub placement: i32,
+    #[prost(enumeration="Gravity", tag="9")]
+    pub gravity: i32,
+    /// 水印的来源，都没有指定时使用内置的 rust logo
+    #[prost(oneof="watermark::Source", tags="3, 4, 5")]
+    pub source: ::core::option::Option<watermark::Source>,
+}
+/// Nested message and enum types in `Watermark`.
+pub mod watermark {
+    /// 文字水印，使用 photon 内置的字体
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[serde(default)]
+    pub struct Text {
+        #[prost(string, tag="1")]
+        pub content: ::prost::alloc::string::String,
+        /// 字号（像素），0 表示使用默认的 24
+        #[prost(uint32, tag="2")]
+        pub size: u32,
+        /// RGBA 颜色，0 表示白色
+        #[prost(fixed32, tag="3")]
+        pub color: u32,
+    }
+    /// 水印的摆放方式
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[repr(i32)]
+    pub enum Placement {
+        /// 水印的左上角放在 (x, y)
+        Absolute = 0,
+        /// 按 gravity 放在角落、边上或者中间，x 和 y 是离边缘的距离
+        Anchored = 1,
+        /// 平铺整张图片，x 和 y 是水印之间的间隔
+        Tiled = 2,
+    }
+    /// 水印的来源，都没有指定时使用内置的 rust logo
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Source {
+        /// 配置文件 [watermarks] 中注册的名字
+        #[prost(string, tag="3")]
+        Name(::prost::alloc::string::String),
+        /// 水印图片的 url，和原图一样需要通过来源检查
+        #[prost(string, tag="4")]
+        Url(::prost::alloc::string::String),
+        #[prost(message, tag="5")]
+        Text(Text),
+    }
+}
+/// 处理输出格式，优先于 Accept 头的 content negotiation
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Format {
+    #[prost(enumeration="format::Type", tag="1")]
+    pub ftype: i32,
+    /// 仅对 JPEG 有效，取值 1-100，0 表示使用默认质量
+    #[prost(uint32, tag="2")]
+    pub quality: u32,
+}
+/// Nested message and enum types in `Format`.
+pub mod format {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[repr(i32)]
+    pub enum Type {
+        Unspecified = 0,
+        Png = 1,
+        Jpeg = 2,
+        Gif = 3,
+        Bmp = 4,
+        Webp = 5,
+    }
+}
+/// 调整亮度，正数变亮，负数变暗，取值 -255 到 255
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Brightness {
+    #[prost(int32, tag="1")]
+    pub brightness: i32,
+}
+/// 在 HSL 色彩空间调整饱和度，正数增加，负数降低，取值 -1.0 到 1.0
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Saturation {
+    #[prost(float, tag="1")]
+    pub amount: f32,
+}
+/// 在 HSL 色彩空间旋转色相
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct HueRotate {
+    #[prost(float, tag="1")]
+    pub degrees: f32,
+}
+/// 高斯模糊
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Blur {
+    #[prost(int32, tag="1")]
+    pub radius: i32,
+}
+/// 锐化
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Sharpen {
+}
+/// 转换成灰度图
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Grayscale {
+}
+/// 顺时针旋转，90/180/270 度是无损的，其它角度会扩大画布，空出来的部分用 background 填充
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct Rotate {
+    #[prost(float, tag="1")]
+    pub degrees: f32,
+    /// RGBA 颜色，比如 0xffffffff 是不透明的白色，0 是透明
+    #[prost(fixed32, tag="2")]
+    pub background: u32,
 }
 /// 一个 spec 可以包含上述的处理方式之一
 #[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
 pub struct Spec {
-    #[prost(oneof="spec::Data", tags="1, 2, 3, 4, 5, 6, 7")]
+    #[prost(oneof="spec::Data", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17")]
     pub data: ::core::option::Option<spec::Data>,
 }
 /// Nested message and enum types in `Spec`.
 pub mod spec {
     #[derive(Clone, PartialEq, ::prost::Oneof)]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     pub enum Data {
         #[prost(message, tag="1")]
         Resize(super::Resize),
@@ -111,5 +363,41 @@ pub mod spec {
         Filter(super::Filter),
         #[prost(message, tag="7")]
         Watermark(super::Watermark),
+        #[prost(message, tag="8")]
+        Format(super::Format),
+        #[prost(message, tag="9")]
+        Brightness(super::Brightness),
+        #[prost(message, tag="10")]
+        Saturation(super::Saturation),
+        #[prost(message, tag="11")]
+        HueRotate(super::HueRotate),
+        #[prost(message, tag="12")]
+        Blur(super::Blur),
+        #[prost(message, tag="13")]
+        Sharpen(super::Sharpen),
+        #[prost(message, tag="14")]
+        Grayscale(super::Grayscale),
+        #[prost(message, tag="15")]
+        Rotate(super::Rotate),
+        #[prost(message, tag="16")]
+        SmartCrop(super::SmartCrop),
+        #[prost(message, tag="17")]
+        FocalCrop(super::FocalCrop),
     }
 }
+/// 图片在画布中的位置，或者裁剪时保留的部分
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum Gravity {
+    Center = 0,
+    North = 1,
+    NorthEast = 2,
+    East = 3,
+    SouthEast = 4,
+    South = 5,
+    SouthWest = 6,
+    West = 7,
+    NorthWest = 8,
+}
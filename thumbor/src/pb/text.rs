@@ -0,0 +1,851 @@
+// ImageSpec 的文本形式，方便手写和在日志里阅读，比如：
+//   resize(300,200,lanczos3)|filter(marine)|watermark(20,20,opacity=0.5)
+// 每个 spec 是 `名字(参数, ...)`，没有参数时可以省略括号，多个 spec 之间用 `|` 分隔
+// 参数可以按顺序给出，也可以写成 `字段名=值`；没有给出的字段使用默认值
+// 字符串用双引号括起来，颜色是 RGBA 的十六进制，比如 ff0000ff
+use super::*;
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, fmt};
+
+const SAMPLE_FILTERS: &[&str] = &[
+    "undefined",
+    "nearest",
+    "triangle",
+    "catmull_rom",
+    "gaussian",
+    "lanczos3",
+];
+const RESIZE_TYPES: &[&str] = &["normal", "seam_carve"];
+const FITS: &[&str] = &["fill", "contain", "cover", "inside"];
+const GRAVITIES: &[&str] = &[
+    "center",
+    "north",
+    "north_east",
+    "east",
+    "south_east",
+    "south",
+    "south_west",
+    "west",
+    "north_west",
+];
+const PLACEMENTS: &[&str] = &["absolute", "anchored", "tiled"];
+const FORMATS: &[&str] = &["auto", "png", "jpeg", "gif", "bmp", "webp"];
+
+// 每种 spec 的名字和字段，按顺序给出的参数依次对应这些字段
+const SPECS: &[(&str, &[&str])] = &[
+    (
+        "resize",
+        &[
+            "width",
+            "height",
+            "filter",
+            "fit",
+            "gravity",
+            "background",
+            "type",
+        ],
+    ),
+    ("crop", &["x1", "y1", "x2", "y2"]),
+    ("smart_crop", &["width", "height"]),
+    ("focal_crop", &["width", "height", "x", "y"]),
+    ("fliph", &[]),
+    ("flipv", &[]),
+    ("contrast", &["contrast"]),
+    ("filter", &["filter"]),
+    (
+        "watermark",
+        &[
+            "x",
+            "y",
+            "opacity",
+            "scale",
+            "placement",
+            "gravity",
+            "name",
+            "url",
+            "text",
+            "size",
+            "color",
+        ],
+    ),
+    ("format", &["type", "quality"]),
+    ("brightness", &["brightness"]),
+    ("saturation", &["amount"]),
+    ("hue_rotate", &["degrees"]),
+    ("blur", &["radius"]),
+    ("sharpen", &[]),
+    ("grayscale", &[]),
+    ("rotate", &["degrees", "background"]),
+];
+
+// 滤镜的名字和 photon 里的一致，Unspecified 写作 none
+fn filter_names() -> Vec<&'static str> {
+    (0..)
+        .map_while(filter::Filter::from_i32)
+        .map(|f| f.to_str().unwrap_or("none"))
+        .collect()
+}
+
+impl fmt::Display for ImageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, spec) in self.specs.iter().enumerate() {
+            if i > 0 {
+                f.write_str("|")?;
+            }
+            write!(f, "{}", spec)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_call() {
+            Some(call) => call.fmt(f),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+// 解析文本形式的 ImageSpec
+pub(super) fn parse(input: &str) -> Result<ImageSpec> {
+    parse_with_presets(input, &HashMap::new())
+}
+
+// `preset:名字` 会展开成配置里对应的 spec，可以和其他 spec 组合使用，比如 preset:avatar|grayscale
+// preset 的内容可以是文本形式或者 base64 形式，但不能再引用其他 preset
+pub(super) fn parse_with_presets(
+    input: &str,
+    presets: &HashMap<String, String>,
+) -> Result<ImageSpec> {
+    let mut parser = Parser { input, pos: 0 };
+    let mut specs = Vec::new();
+    loop {
+        if let Some(name) = parser.preset() {
+            let preset = presets
+                .get(&name)
+                .ok_or_else(|| anyhow!("unknown preset `{}`", name))?;
+            let spec = ImageSpec::try_from(preset.as_str())
+                .map_err(|e| anyhow!("invalid preset `{}`: {}", name, e))?;
+            specs.extend(spec.specs);
+        } else {
+            let (name, args) = parser.call()?;
+            specs.push(Spec::from_call(&name, args)?);
+        }
+        parser.skip_whitespace();
+        if parser.eat('|') {
+            continue;
+        }
+        if parser.pos == input.len() {
+            return Ok(ImageSpec::new(specs));
+        }
+        bail!("unexpected `{}` at {}", parser.rest(), parser.pos);
+    }
+}
+
+// 渲染时的一个字段，默认值不需要写出来
+struct Field {
+    name: &'static str,
+    value: String,
+    default: bool,
+}
+
+// 一个 spec 的文本形式
+struct Call {
+    name: &'static str,
+    fields: Vec<Field>,
+    // 前 required 个字段总是按顺序写出
+    required: usize,
+    // 前 positional 个字段可以按顺序写出，之后的字段总是写成 `字段名=值`
+    positional: usize,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        // 按顺序写出的字段一直到最后一个不是默认值的为止
+        let last = self.fields[..self.positional]
+            .iter()
+            .rposition(|field| !field.default)
+            .map_or(0, |i| i + 1);
+        let count = last.max(self.required);
+        let mut args: Vec<String> = self.fields[..count]
+            .iter()
+            .map(|field| field.value.clone())
+            .collect();
+        args.extend(
+            self.fields[count..]
+                .iter()
+                .filter(|field| !field.default)
+                .map(|field| format!("{}={}", field.name, field.value)),
+        );
+        write!(f, "({})", args.join(","))
+    }
+}
+
+fn uint(name: &'static str, value: u32) -> Field {
+    Field {
+        name,
+        value: value.to_string(),
+        default: value == 0,
+    }
+}
+
+fn int(name: &'static str, value: i32) -> Field {
+    Field {
+        name,
+        value: value.to_string(),
+        default: value == 0,
+    }
+}
+
+fn float(name: &'static str, value: f32) -> Field {
+    Field {
+        name,
+        value: value.to_string(),
+        default: value == 0.0,
+    }
+}
+
+// 枚举写成名字，不认识的取值直接写成数字
+fn enumeration(name: &'static str, value: i32, names: &[&str]) -> Field {
+    let text = usize::try_from(value)
+        .ok()
+        .and_then(|i| names.get(i))
+        .map_or_else(|| value.to_string(), |s| s.to_string());
+    Field {
+        name,
+        value: text,
+        default: value == 0,
+    }
+}
+
+fn color(name: &'static str, value: u32) -> Field {
+    Field {
+        name,
+        value: format!("{:08x}", value),
+        default: value == 0,
+    }
+}
+
+fn string(name: &'static str, value: &str) -> Field {
+    Field {
+        name,
+        value: format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        default: value.is_empty(),
+    }
+}
+
+impl Spec {
+    fn to_call(&self) -> Option<Call> {
+        let (name, fields, required, positional) = match self.data.as_ref()? {
+            spec::Data::Resize(v) => (
+                "resize",
+                vec![
+                    uint("width", v.width),
+                    uint("height", v.height),
+                    enumeration("filter", v.filter, SAMPLE_FILTERS),
+                    enumeration("fit", v.fit, FITS),
+                    enumeration("gravity", v.gravity, GRAVITIES),
+                    color("background", v.background),
+                    enumeration("type", v.rtype, RESIZE_TYPES),
+                ],
+                2,
+                3,
+            ),
+            spec::Data::Crop(v) => (
+                "crop",
+                vec![
+                    uint("x1", v.x1),
+                    uint("y1", v.y1),
+                    uint("x2", v.x2),
+                    uint("y2", v.y2),
+                ],
+                4,
+                4,
+            ),
+            spec::Data::SmartCrop(v) => (
+                "smart_crop",
+                vec![uint("width", v.width), uint("height", v.height)],
+                2,
+                2,
+            ),
+            spec::Data::FocalCrop(v) => (
+                "focal_crop",
+                vec![
+                    uint("width", v.width),
+                    uint("height", v.height),
+                    float("x", v.x),
+                    float("y", v.y),
+                ],
+                4,
+                4,
+            ),
+            spec::Data::Fliph(_) => ("fliph", vec![], 0, 0),
+            spec::Data::Flipv(_) => ("flipv", vec![], 0, 0),
+            spec::Data::Contrast(v) => ("contrast", vec![float("contrast", v.contrast)], 1, 1),
+            spec::Data::Filter(v) => (
+                "filter",
+                vec![enumeration("filter", v.filter, &filter_names())],
+                1,
+                1,
+            ),
+            spec::Data::Watermark(v) => {
+                let (name, url, text) = match &v.source {
+                    Some(watermark::Source::Name(name)) => (name.as_str(), "", None),
+                    Some(watermark::Source::Url(url)) => ("", url.as_str(), None),
+                    Some(watermark::Source::Text(text)) => ("", "", Some(text)),
+                    None => ("", "", None),
+                };
+                let mut fields = vec![
+                    uint("x", v.x),
+                    uint("y", v.y),
+                    float("opacity", v.opacity),
+                    float("scale", v.scale),
+                    enumeration("placement", v.placement, PLACEMENTS),
+                    enumeration("gravity", v.gravity, GRAVITIES),
+                    string("name", name),
+                    string("url", url),
+                ];
+                if let Some(text) = text {
+                    // 空字符串的文字水印也要写出来，否则会变成内置的水印
+                    let mut content = string("text", &text.content);
+                    content.default = false;
+                    fields.push(content);
+                    fields.push(uint("size", text.size));
+                    fields.push(color("color", text.color));
+                }
+                ("watermark", fields, 2, 2)
+            }
+            spec::Data::Format(v) => (
+                "format",
+                vec![
+                    enumeration("type", v.ftype, FORMATS),
+                    uint("quality", v.quality),
+                ],
+                1,
+                2,
+            ),
+            spec::Data::Brightness(v) => {
+                ("brightness", vec![int("brightness", v.brightness)], 1, 1)
+            }
+            spec::Data::Saturation(v) => ("saturation", vec![float("amount", v.amount)], 1, 1),
+            spec::Data::HueRotate(v) => ("hue_rotate", vec![float("degrees", v.degrees)], 1, 1),
+            spec::Data::Blur(v) => ("blur", vec![int("radius", v.radius)], 1, 1),
+            spec::Data::Sharpen(_) => ("sharpen", vec![], 0, 0),
+            spec::Data::Grayscale(_) => ("grayscale", vec![], 0, 0),
+            spec::Data::Rotate(v) => (
+                "rotate",
+                vec![
+                    float("degrees", v.degrees),
+                    color("background", v.background),
+                ],
+                1,
+                2,
+            ),
+        };
+        Some(Call {
+            name,
+            fields,
+            required,
+            positional,
+        })
+    }
+
+    fn from_call(name: &str, args: Vec<(Option<String>, String)>) -> Result<Self> {
+        let mut f = Fields::new(name, args)?;
+        let data = match name {
+            "resize" => spec::Data::Resize(Resize {
+                width: f.uint("width")?,
+                height: f.uint("height")?,
+                filter: f.enumeration("filter", SAMPLE_FILTERS)?,
+                fit: f.enumeration("fit", FITS)?,
+                gravity: f.enumeration("gravity", GRAVITIES)?,
+                background: f.color("background")?,
+                rtype: f.enumeration("type", RESIZE_TYPES)?,
+            }),
+            "crop" => spec::Data::Crop(Crop {
+                x1: f.uint("x1")?,
+                y1: f.uint("y1")?,
+                x2: f.uint("x2")?,
+                y2: f.uint("y2")?,
+            }),
+            "smart_crop" => spec::Data::SmartCrop(SmartCrop {
+                width: f.uint("width")?,
+                height: f.uint("height")?,
+            }),
+            "focal_crop" => spec::Data::FocalCrop(FocalCrop {
+                width: f.uint("width")?,
+                height: f.uint("height")?,
+                x: f.float("x")?,
+                y: f.float("y")?,
+            }),
+            "fliph" => spec::Data::Fliph(Fliph {}),
+            "flipv" => spec::Data::Flipv(Flipv {}),
+            "contrast" => spec::Data::Contrast(Contrast {
+                contrast: f.float("contrast")?,
+            }),
+            "filter" => spec::Data::Filter(Filter {
+                filter: f.enumeration("filter", &filter_names())?,
+            }),
+            "watermark" => {
+                let name = f.string("name");
+                let url = f.string("url");
+                let text = f.string("text");
+                let size = f.uint("size")?;
+                let color = f.color("color")?;
+                let source = match (name, url, text) {
+                    (None, None, None) if size == 0 && color == 0 => None,
+                    (None, None, None) => bail!("`size` and `color` only apply to text watermarks"),
+                    (Some(name), None, None) => Some(watermark::Source::Name(name)),
+                    (None, Some(url), None) => Some(watermark::Source::Url(url)),
+                    (None, None, Some(content)) => Some(watermark::Source::Text(watermark::Text {
+                        content,
+                        size,
+                        color,
+                    })),
+                    _ => bail!("only one of `name`, `url` and `text` can be given"),
+                };
+                spec::Data::Watermark(Watermark {
+                    x: f.uint("x")?,
+                    y: f.uint("y")?,
+                    opacity: f.float("opacity")?,
+                    scale: f.float("scale")?,
+                    placement: f.enumeration("placement", PLACEMENTS)?,
+                    gravity: f.enumeration("gravity", GRAVITIES)?,
+                    source,
+                })
+            }
+            "format" => spec::Data::Format(Format {
+                ftype: f.enumeration("type", FORMATS)?,
+                quality: f.uint("quality")?,
+            }),
+            "brightness" => spec::Data::Brightness(Brightness {
+                brightness: f.int("brightness")?,
+            }),
+            "saturation" => spec::Data::Saturation(Saturation {
+                amount: f.float("amount")?,
+            }),
+            "hue_rotate" => spec::Data::HueRotate(HueRotate {
+                degrees: f.float("degrees")?,
+            }),
+            "blur" => spec::Data::Blur(Blur {
+                radius: f.int("radius")?,
+            }),
+            "sharpen" => spec::Data::Sharpen(Sharpen {}),
+            "grayscale" => spec::Data::Grayscale(Grayscale {}),
+            "rotate" => spec::Data::Rotate(Rotate {
+                degrees: f.float("degrees")?,
+                background: f.color("background")?,
+            }),
+            _ => unreachable!("checked by Fields::new"),
+        };
+        Ok(Spec { data: Some(data) })
+    }
+}
+
+// 解析时按字段名取参数，没有给出的字段为默认值
+struct Fields {
+    name: String,
+    values: HashMap<&'static str, String>,
+}
+
+impl Fields {
+    fn new(name: &str, args: Vec<(Option<String>, String)>) -> Result<Self> {
+        let names = SPECS
+            .iter()
+            .find(|(spec, _)| *spec == name)
+            .map(|(_, names)| *names)
+            .ok_or_else(|| anyhow!("unknown spec `{}`", name))?;
+        let mut values = HashMap::new();
+        for (i, (key, value)) in args.into_iter().enumerate() {
+            let field = match key {
+                Some(key) => names.iter().find(|n| **n == key).copied(),
+                None => names.get(i).copied(),
+            }
+            .ok_or_else(|| anyhow!("unexpected argument {} for `{}`", i + 1, name))?;
+            if values.insert(field, value).is_some() {
+                bail!("`{}` is given more than once for `{}`", field, name);
+            }
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            values,
+        })
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, field: &str) -> Result<Option<T>> {
+        match self.values.remove(field) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| anyhow!("invalid {}.{}: `{}`", self.name, field, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn uint(&mut self, field: &str) -> Result<u32> {
+        Ok(self.parse(field)?.unwrap_or_default())
+    }
+
+    fn int(&mut self, field: &str) -> Result<i32> {
+        Ok(self.parse(field)?.unwrap_or_default())
+    }
+
+    fn float(&mut self, field: &str) -> Result<f32> {
+        Ok(self.parse(field)?.unwrap_or_default())
+    }
+
+    // 枚举可以写成名字，也可以直接写数字
+    fn enumeration(&mut self, field: &str, names: &[&str]) -> Result<i32> {
+        match self.values.get(field) {
+            Some(value) => match names.iter().position(|n| n == value) {
+                Some(i) => {
+                    self.values.remove(field);
+                    Ok(i as i32)
+                }
+                None => Ok(self.parse(field)?.unwrap_or_default()),
+            },
+            None => Ok(0),
+        }
+    }
+
+    // 6 位（不透明）或者 8 位（带 alpha）的十六进制 RGBA
+    fn color(&mut self, field: &str) -> Result<u32> {
+        let value = match self.values.remove(field) {
+            Some(value) => value,
+            None => return Ok(0),
+        };
+        let hex = value.trim_start_matches('#');
+        let color = match hex.len() {
+            6 => u32::from_str_radix(hex, 16).map(|rgb| rgb << 8 | 0xff),
+            8 => u32::from_str_radix(hex, 16),
+            _ => bail!("invalid {}.{}: `{}`", self.name, field, value),
+        };
+        color.map_err(|_| anyhow!("invalid {}.{}: `{}`", self.name, field, value))
+    }
+
+    fn string(&mut self, field: &str) -> Option<String> {
+        self.values.remove(field)
+    }
+}
+
+// 手写的递归下降解析器
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // `preset:名字`，不是的话不移动位置
+    fn preset(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let rest = self.rest().strip_prefix("preset:")?;
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        self.pos += "preset:".len() + len;
+        Some(rest[..len].to_owned())
+    }
+
+    // `名字` 或者 `名字(参数, ...)`
+    fn call(&mut self) -> Result<(String, Vec<(Option<String>, String)>)> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            bail!("expected a spec name at {}", self.pos);
+        }
+        let name = rest[..len].to_owned();
+        self.pos += len;
+
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if !self.eat('(') {
+            return Ok((name, args));
+        }
+        self.skip_whitespace();
+        if self.eat(')') {
+            return Ok((name, args));
+        }
+        loop {
+            args.push(self.arg()?);
+            self.skip_whitespace();
+            if self.eat(',') {
+                continue;
+            }
+            if self.eat(')') {
+                return Ok((name, args));
+            }
+            bail!("expected `,` or `)` at {}", self.pos);
+        }
+    }
+
+    // `值` 或者 `字段名=值`
+    fn arg(&mut self) -> Result<(Option<String>, String)> {
+        let first = self.value()?;
+        self.skip_whitespace();
+        match first {
+            Value::Raw(key) if self.eat('=') => Ok((Some(key), self.value()?.into_string())),
+            value => Ok((None, value.into_string())),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        if !self.eat('"') {
+            let rest = self.rest();
+            let len = rest
+                .find([',', ')', '=', '|', '(', '"'])
+                .unwrap_or(rest.len());
+            self.pos += len;
+            return Ok(Value::Raw(rest[..len].trim().to_owned()));
+        }
+
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(Value::Quoted(s));
+                }
+                '\\' => match chars.next() {
+                    Some((_, c)) => s.push(c),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        bail!("unterminated string")
+    }
+}
+
+enum Value {
+    Raw(String),
+    Quoted(String),
+}
+
+impl Value {
+    fn into_string(self) -> String {
+        match self {
+            Value::Raw(s) | Value::Quoted(s) => s,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_spec() -> Vec<Spec> {
+        vec![
+            Spec::new_resize(300, 200, resize::SampleFilter::Lanczos3),
+            Spec::new_resize_fit(
+                300,
+                0,
+                resize::Fit::Cover,
+                Gravity::NorthEast,
+                resize::SampleFilter::Undefined,
+            ),
+            Spec::new_resize_seam_carve(100, 100),
+            Spec {
+                data: Some(spec::Data::Crop(Crop {
+                    x1: 0,
+                    y1: 10,
+                    x2: 20,
+                    y2: 30,
+                })),
+            },
+            Spec::new_smart_crop(64, 64),
+            Spec::new_focal_crop(64, 32, 0.25, 0.0),
+            Spec {
+                data: Some(spec::Data::Fliph(Fliph {})),
+            },
+            Spec {
+                data: Some(spec::Data::Flipv(Flipv {})),
+            },
+            Spec {
+                data: Some(spec::Data::Contrast(Contrast { contrast: -12.5 })),
+            },
+            Spec::new_filter(filter::Filter::PastelPink),
+            Spec::new_watermark(20, 20),
+            Spec {
+                data: Some(spec::Data::Watermark(Watermark {
+                    x: 8,
+                    y: 0,
+                    opacity: 0.5,
+                    scale: 0.2,
+                    placement: watermark::Placement::Anchored as i32,
+                    gravity: Gravity::SouthWest as i32,
+                    source: Some(watermark::Source::Url(
+                        "https://example.com/a.png?x=(1),\"2\"|3".to_owned(),
+                    )),
+                })),
+            },
+            Spec {
+                data: Some(spec::Data::Watermark(Watermark {
+                    source: Some(watermark::Source::Text(watermark::Text {
+                        content: "© thumbor \\o/".to_owned(),
+                        size: 32,
+                        color: 0x336699cc,
+                    })),
+                    placement: watermark::Placement::Tiled as i32,
+                    ..Default::default()
+                })),
+            },
+            Spec {
+                data: Some(spec::Data::Watermark(Watermark {
+                    source: Some(watermark::Source::Name("logo".to_owned())),
+                    ..Default::default()
+                })),
+            },
+            Spec::new_format(format::Type::Jpeg, 75),
+            Spec::new_format(format::Type::Webp, 0),
+            Spec::new_brightness(-40),
+            Spec::new_saturation(0.5),
+            Spec::new_hue_rotate(-90.0),
+            Spec::new_blur(3),
+            Spec::new_sharpen(),
+            Spec::new_grayscale(),
+            Spec::new_rotate(90.0, 0),
+            Spec::new_rotate(12.5, 0xffffffff),
+        ]
+    }
+
+    #[test]
+    fn text_should_round_trip_with_protobuf() {
+        for spec in every_spec() {
+            let image_spec = ImageSpec::new(vec![spec]);
+            let text = image_spec.to_string();
+            let parsed = ImageSpec::try_from(text.as_str()).unwrap();
+            assert_eq!(parsed, image_spec, "{}", text);
+
+            // 和 base64 的 protobuf 形式解析出来的结果完全一样
+            let encoded = String::from(&image_spec);
+            assert_eq!(ImageSpec::try_from(encoded.as_str()).unwrap(), parsed);
+        }
+
+        let image_spec = ImageSpec::new(every_spec());
+        let text = image_spec.to_string();
+        assert_eq!(ImageSpec::try_from(text.as_str()).unwrap(), image_spec);
+    }
+
+    #[test]
+    fn text_should_be_readable() {
+        let image_spec = ImageSpec::new(vec![
+            Spec::new_resize(300, 200, resize::SampleFilter::Lanczos3),
+            Spec::new_filter(filter::Filter::Marine),
+            Spec::new_watermark(20, 20),
+            Spec {
+                data: Some(spec::Data::Fliph(Fliph {})),
+            },
+            Spec::new_rotate(45.0, 0xff0000ff),
+        ]);
+        assert_eq!(
+            image_spec.to_string(),
+            "resize(300,200,lanczos3)|filter(marine)|watermark(20,20)|fliph|rotate(45,ff0000ff)"
+        );
+    }
+
+    #[test]
+    fn text_should_accept_named_arguments() {
+        let parsed = parse(
+            "resize( 300 , 200, fit = cover, gravity=north ) | watermark(name=logo, opacity=0.5)",
+        )
+        .unwrap();
+        let expected = ImageSpec::new(vec![
+            Spec::new_resize_fit(
+                300,
+                200,
+                resize::Fit::Cover,
+                Gravity::North,
+                resize::SampleFilter::Undefined,
+            ),
+            Spec {
+                data: Some(spec::Data::Watermark(Watermark {
+                    opacity: 0.5,
+                    source: Some(watermark::Source::Name("logo".to_owned())),
+                    ..Default::default()
+                })),
+            },
+        ]);
+        assert_eq!(parsed, expected);
+        assert_eq!(
+            parse("rotate(90,#ff0000)").unwrap().specs[0],
+            Spec::new_rotate(90.0, 0xff0000ff)
+        );
+    }
+
+    #[test]
+    fn invalid_text_should_be_rejected() {
+        let cases = [
+            "resize(300,200",
+            "resize(a,200)",
+            "resize(300,200,unknown_filter)",
+            "resize(1,2,3,4,5,6,7,8)",
+            "resize(300,width=200)",
+            "unknown(1)",
+            "watermark(name=a,url=b)",
+            "watermark(size=20)",
+            "fliph|",
+            "blur(3) blur(4)",
+            "watermark(text=\"abc)",
+        ];
+        for case in cases {
+            assert!(parse(case).is_err(), "{} should be invalid", case);
+        }
+    }
+
+    #[test]
+    fn presets_should_be_expanded() {
+        let presets = HashMap::from([
+            (
+                "avatar".to_owned(),
+                "resize(128,128,fit=cover)|format(webp)".to_owned(),
+            ),
+            (
+                "banner".to_owned(),
+                String::from(&ImageSpec::new(vec![Spec::new_blur(2)])),
+            ),
+            ("nested".to_owned(), "preset:avatar".to_owned()),
+        ]);
+        let parsed = parse_with_presets("preset:avatar|grayscale|preset:banner", &presets).unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "resize(128,128,fit=cover)|format(webp)|grayscale|blur(2)"
+        );
+
+        assert!(parse_with_presets("preset:unknown", &presets).is_err());
+        assert!(parse_with_presets("preset:nested", &presets).is_err());
+        assert!(parse("preset:avatar").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use base64::{decode_config, encode_config, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// /info 请求签名时使用的 spec，它不是合法的 spec，签名不能用于生成缩略图
+const INFO_SPEC: &str = "info";
+
+// 对 url 签名，防止别人随意构造 spec 和 url，把服务当成开放代理使用
+// 签名的内容是 `spec/url`，其中 spec 和 url 都是 percent decode 之后的原始内容
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    pub fn sign(&self, spec: &str, url: &str) -> String {
+        let mac = self.mac(spec, url).finalize().into_bytes();
+        encode_config(mac, URL_SAFE_NO_PAD)
+    }
+
+    // 校验签名，比较时使用常量时间，避免时序攻击
+    pub fn verify(&self, signature: &str, spec: &str, url: &str) -> bool {
+        match decode_config(signature, URL_SAFE_NO_PAD) {
+            Ok(tag) => self.mac(spec, url).verify(&tag).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // 生成带签名的访问路径，文本形式的 spec 里可能有 `/`、引号和空格，和 url 一样需要转义
+    pub fn signed_path(&self, spec: &str, url: &str) -> String {
+        let signature = self.sign(spec, url);
+        let spec = percent_encode(spec.as_bytes(), NON_ALPHANUMERIC);
+        let url = percent_encode(url.as_bytes(), NON_ALPHANUMERIC);
+        format!("/image/{}/{}/{}", signature, spec, url)
+    }
+
+    pub fn verify_info(&self, signature: &str, url: &str) -> bool {
+        self.verify(signature, INFO_SPEC, url)
+    }
+
+    // 生成带签名的 /info 访问路径
+    pub fn signed_info_path(&self, url: &str) -> String {
+        let signature = self.sign(INFO_SPEC, url);
+        let url = percent_encode(url.as_bytes(), NON_ALPHANUMERIC);
+        format!("/info/{}/{}", signature, url)
+    }
+
+    fn mac(&self, spec: &str, url: &str) -> HmacSha256 {
+        // HMAC 可以接受任意长度的 key，这里不会失败
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(spec.as_bytes());
+        mac.update(b"/");
+        mac.update(url.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use percent_encoding::percent_decode_str;
+
+    const URL: &str = "https://example.com/a.jpg";
+
+    #[test]
+    fn signed_url_should_be_verified() {
+        let signer = Signer::new("secret");
+        let signature = signer.sign("spec", URL);
+        assert!(signer.verify(&signature, "spec", URL));
+    }
+
+    #[test]
+    fn tampered_url_should_be_rejected() {
+        let signer = Signer::new("secret");
+        let signature = signer.sign("spec", URL);
+        assert!(!signer.verify(&signature, "other", URL));
+        assert!(!signer.verify(&signature, "spec", "https://example.com/b.jpg"));
+        assert!(!signer.verify("not-base64!", "spec", URL));
+        assert!(!Signer::new("another").verify(&signature, "spec", URL));
+    }
+
+    #[test]
+    fn info_signature_should_not_be_used_for_images() {
+        let signer = Signer::new("secret");
+        let path = signer.signed_info_path(URL);
+        let signature = path.split('/').nth(2).unwrap();
+        assert!(signer.verify_info(signature, URL));
+        assert!(!signer.verify_info(signature, "https://example.com/b.jpg"));
+        // 图片的签名也不能用于 /info
+        assert!(!signer.verify(signature, "spec", URL));
+        assert!(!signer.verify_info(&signer.sign("spec", URL), URL));
+    }
+
+    #[test]
+    fn signed_path_should_encode_text_spec() {
+        let signer = Signer::new("secret");
+        let spec = r#"resize(64,64)|watermark(url="https://example.com/logo.png")"#;
+        let path = signer.signed_path(spec, URL);
+        // spec 里的 `/` 被转义，路径仍然是 /image/签名/spec/url
+        let segments: Vec<_> = path.split('/').collect();
+        assert_eq!(segments.len(), 5);
+        let decoded = percent_decode_str(segments[3]).decode_utf8().unwrap();
+        assert_eq!(decoded, spec);
+        assert!(signer.verify(segments[2], &decoded, URL));
+    }
+}
@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Url;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error;
+
+mod file;
+mod guard;
+mod http;
+mod s3;
+
+pub use file::FileSource;
+pub use guard::{GuardError, SourceGuard};
+pub use http::HttpSource;
+pub use s3::{S3Config, S3Source};
+
+// 获取到的原图，以及它可以缓存的时间（None 表示不能缓存）
+pub type Fetched = (Bytes, Option<Duration>);
+
+// ImageSource trait：原图可以来自 http、本地文件或者对象存储，未来添加新的来源只需要实现它
+#[async_trait]
+pub trait ImageSource: Send + Sync {
+    async fn fetch(&self, url: &Url) -> Result<Fetched, FetchError>;
+}
+
+// 根据 url 的 scheme 选择对应的 ImageSource，没有注册的 scheme 一律拒绝
+#[derive(Default)]
+pub struct Sources {
+    sources: HashMap<String, Arc<dyn ImageSource>>,
+}
+
+impl Sources {
+    pub fn register(&mut self, scheme: &str, source: Arc<dyn ImageSource>) {
+        self.sources.insert(scheme.to_owned(), source);
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Fetched, FetchError> {
+        let url = Url::parse(url).map_err(|e| FetchError::Upstream(e.to_string()))?;
+        match self.sources.get(url.scheme()) {
+            Some(source) => source.fetch(&url).await,
+            None => Err(FetchError::Forbidden(format!(
+                "scheme `{}` is not supported",
+                url.scheme()
+            ))),
+        }
+    }
+}
+
+// 下载原图时的限制
+#[derive(Debug, Clone, Copy)]
+pub struct FetchLimits {
+    // 原图最大的字节数，超过时立即中止下载
+    pub max_bytes: u64,
+    // 单次下载（包括跳转）的超时时间
+    pub timeout: Duration,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 32 * 1024 * 1024,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+// 获取原图时的错误，需要在多个等待同一次下载的请求之间共享，所以只保存错误信息
+#[derive(Debug, Clone, Error)]
+pub enum FetchError {
+    // 被 SourceGuard 拒绝，或者访问了不允许的路径
+    #[error("forbidden source: {0}")]
+    Forbidden(String),
+
+    #[error("failed to fetch source: {0}")]
+    Upstream(String),
+
+    #[error("source is larger than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("fetching source timed out after {0:?}")]
+    Timeout(Duration),
+}
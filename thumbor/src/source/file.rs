@@ -0,0 +1,142 @@
+use super::{FetchError, Fetched, ImageSource};
+use async_trait::async_trait;
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use std::{io, path::PathBuf, time::Duration};
+use tokio::io::AsyncReadExt;
+
+// 从本地目录读取原图：`file:///a/b.jpg` 对应 root 下的 `a/b.jpg`，不能访问 root 之外的文件
+pub struct FileSource {
+    root: PathBuf,
+    max_bytes: u64,
+    ttl: Duration,
+}
+
+impl FileSource {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: u64, ttl: Duration) -> io::Result<Self> {
+        // root 本身也可能是符号链接，统一使用真实路径做比较
+        let root = root.into().canonicalize()?;
+        Ok(Self {
+            root,
+            max_bytes,
+            ttl,
+        })
+    }
+
+    fn resolve(&self, url: &Url) -> Result<PathBuf, FetchError> {
+        let forbidden = || FetchError::Forbidden(url.to_string());
+        if matches!(url.host_str(), Some(host) if !host.is_empty() && host != "localhost") {
+            return Err(forbidden());
+        }
+        let mut path = self.root.clone();
+        for segment in url.path_segments().into_iter().flatten() {
+            let segment = percent_decode_str(segment)
+                .decode_utf8()
+                .map_err(|_| forbidden())?;
+            match segment.as_ref() {
+                "" | "." => continue,
+                // url 解析时已经处理过 `..`，这里再防一下编码过的分隔符
+                s if s == ".." || s.contains(&['/', '\\', '\0'][..]) => return Err(forbidden()),
+                s => path.push(s),
+            }
+        }
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl ImageSource for FileSource {
+    async fn fetch(&self, url: &Url) -> Result<Fetched, FetchError> {
+        let path = self.resolve(url)?;
+        // 解析符号链接之后依然要在 root 下面
+        let path = tokio::fs::canonicalize(&path)
+            .await
+            .map_err(|e| FetchError::Upstream(e.to_string()))?;
+        if !path.starts_with(&self.root) {
+            return Err(FetchError::Forbidden(url.to_string()));
+        }
+
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| FetchError::Upstream(e.to_string()))?;
+        // 多读一个字节，用来判断文件是否超过了限制
+        let mut buf = Vec::new();
+        file.take(self.max_bytes + 1)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(|e| FetchError::Upstream(e.to_string()))?;
+        if buf.len() as u64 > self.max_bytes {
+            return Err(FetchError::TooLarge(self.max_bytes));
+        }
+        Ok((Bytes::from(buf), Some(self.ttl)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn file_under_root_should_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("photos")).unwrap();
+        fs::write(dir.path().join("photos/a.jpg"), b"hello").unwrap();
+
+        let source = FileSource::new(dir.path(), 1024, TTL).unwrap();
+        let (data, ttl) = source.fetch(&url("file:///photos/a.jpg")).await.unwrap();
+        assert_eq!(data, Bytes::from_static(b"hello"));
+        assert_eq!(ttl, Some(TTL));
+
+        let source = FileSource::new(dir.path(), 4, TTL).unwrap();
+        assert!(matches!(
+            source.fetch(&url("file:///photos/a.jpg")).await,
+            Err(FetchError::TooLarge(4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn path_traversal_should_be_forbidden() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(dir.path().join("secret"), b"secret").unwrap();
+        let source = FileSource::new(&root, 1024, TTL).unwrap();
+
+        for u in ["file:///..%2Fsecret", "file://other-host/secret"] {
+            assert!(
+                matches!(source.fetch(&url(u)).await, Err(FetchError::Forbidden(_))),
+                "{} should be forbidden",
+                u
+            );
+        }
+        // `..` 在解析 url 时就被去掉了，最多只能回到 root
+        assert!(matches!(
+            source.fetch(&url("file:///../secret")).await,
+            Err(FetchError::Upstream(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_out_of_root_should_be_forbidden() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(dir.path().join("secret"), b"secret").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), root.join("link")).unwrap();
+
+        let source = FileSource::new(&root, 1024, TTL).unwrap();
+        assert!(matches!(
+            source.fetch(&url("file:///link")).await,
+            Err(FetchError::Forbidden(_))
+        ));
+    }
+}
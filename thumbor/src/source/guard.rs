@@ -0,0 +1,345 @@
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect::Policy,
+    Url,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GuardError {
+    #[error("scheme `{0}` is not allowed")]
+    Scheme(String),
+
+    #[error("url `{0}` is not in the allow list")]
+    NotAllowed(String),
+
+    #[error("url has no host")]
+    MissingHost,
+
+    #[error("`{0}` is not a valid url prefix")]
+    InvalidPrefix(String),
+
+    #[error("`{0}` is not a public address")]
+    NonPublic(String),
+
+    #[error("more than {0} redirects")]
+    TooManyRedirects(usize),
+}
+
+// 限制服务器可以访问的原图地址，防止 SSRF
+#[derive(Debug, Clone)]
+pub struct SourceGuard {
+    // 允许的 scheme
+    pub schemes: Vec<String>,
+    // 允许的 host，`*.example.com` 可以匹配所有子域名；和 prefixes 都为空时允许任意 host
+    pub hosts: Vec<String>,
+    // 允许的 url 前缀，比如 `https://cdn.example.com/images/`
+    // scheme、host 和端口必须完全一致，path 按 `/` 分段匹配
+    pub prefixes: Vec<Url>,
+    // 是否允许访问内网、loopback、link-local 等非公网地址
+    pub allow_private: bool,
+    pub max_redirects: usize,
+}
+
+impl Default for SourceGuard {
+    fn default() -> Self {
+        Self {
+            schemes: vec!["http".to_owned(), "https".to_owned()],
+            hosts: Vec::new(),
+            prefixes: Vec::new(),
+            allow_private: false,
+            max_redirects: 5,
+        }
+    }
+}
+
+impl SourceGuard {
+    // 包含 `://` 的条目作为 url 前缀，其他的作为 host
+    pub fn allow<I, S>(mut self, entries: I) -> Result<Self, GuardError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for entry in entries {
+            let entry = entry.into();
+            if entry.contains("://") {
+                match Url::parse(&entry) {
+                    Ok(url) if url.has_host() => self.prefixes.push(url),
+                    _ => return Err(GuardError::InvalidPrefix(entry)),
+                }
+            } else {
+                self.hosts.push(entry.to_ascii_lowercase());
+            }
+        }
+        Ok(self)
+    }
+
+    // 检查 url 本身，域名解析之后的地址由 GuardedResolver 检查
+    pub fn check_url(&self, url: &Url) -> Result<(), GuardError> {
+        if !self.schemes.iter().any(|s| s == url.scheme()) {
+            return Err(GuardError::Scheme(url.scheme().to_owned()));
+        }
+        let host = url.host_str().ok_or(GuardError::MissingHost)?;
+        if !self.is_allowed(url, host) {
+            return Err(GuardError::NotAllowed(url.to_string()));
+        }
+        // 直接使用 ip 地址时不会经过域名解析，需要在这里检查
+        if !self.allow_private {
+            // ipv6 地址在 url 里带有方括号
+            let ip = host.trim_start_matches('[').trim_end_matches(']');
+            if let Ok(ip) = ip.parse::<IpAddr>() {
+                if !is_public(ip) {
+                    return Err(GuardError::NonPublic(ip.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 跳转之后的地址同样需要检查，并且限制跳转的次数
+    pub fn redirect_policy(self: &Arc<Self>) -> Policy {
+        let guard = self.clone();
+        Policy::custom(move |attempt| {
+            if attempt.previous().len() > guard.max_redirects {
+                let e = GuardError::TooManyRedirects(guard.max_redirects);
+                return attempt.error(e);
+            }
+            match guard.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+
+    pub fn resolver(&self) -> GuardedResolver {
+        GuardedResolver {
+            allow_private: self.allow_private,
+        }
+    }
+
+    fn is_allowed(&self, url: &Url, host: &str) -> bool {
+        if self.hosts.is_empty() && self.prefixes.is_empty() {
+            return true;
+        }
+        let host = host.to_ascii_lowercase();
+        let host_allowed = self
+            .hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(domain) => host.ends_with(&format!(".{}", domain)),
+                None => &host == allowed,
+            });
+        host_allowed || self.prefixes.iter().any(|p| has_prefix(url, p))
+    }
+}
+
+// 不能直接比较字符串，否则 `https://cdn.example.com` 会匹配到 `https://cdn.example.com.evil.io`
+fn has_prefix(url: &Url, prefix: &Url) -> bool {
+    if url.scheme() != prefix.scheme()
+        || url.host_str() != prefix.host_str()
+        || url.port_or_known_default() != prefix.port_or_known_default()
+    {
+        return false;
+    }
+    let (path, prefix) = (url.path(), prefix.path());
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+// 域名解析时过滤掉非公网地址，连接时只会使用检查过的地址，
+// 这样也能防止 DNS rebinding：检查和连接用的是同一次解析的结果
+pub struct GuardedResolver {
+    allow_private: bool,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_private = self.allow_private;
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            let addrs: Vec<_> = addrs
+                .filter(|addr| allow_private || is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                let e: Box<dyn std::error::Error + Send + Sync> =
+                    Box::new(GuardError::NonPublic(host.to_owned()));
+                return Err(e);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// 标准库里的 is_global 还没有稳定，只好自己判断
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // 0.0.0.0/8
+        || a == 0
+        // 100.64.0.0/10，运营商级 NAT
+        || (a == 100 && (b & 0xc0) == 64)
+        // 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15，基准测试
+        || (a == 198 && (b & 0xfe) == 18)
+        // 240.0.0.0/4，保留地址
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    // 内嵌 ipv4 地址的前缀，实际访问的是 ipv4 地址
+    if let Some(v4) = embedded_v4(ip) {
+        return is_public_v4(v4);
+    }
+    let segments = ip.segments();
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // fc00::/7，unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // fe80::/10，link-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // 2001::/32，Teredo 隧道，地址里的 ipv4 经过了混淆，整个范围都不允许
+        || (segments[0] == 0x2001 && segments[1] == 0)
+        // 2001:db8::/32，文档示例
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+// ::ffff:a.b.c.d、::a.b.c.d、64:ff9b::a.b.c.d 以及 2002:aabb:ccdd::/48（6to4）里的 ipv4 地址
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |hi: u16, lo: u16| {
+        let [a, b] = hi.to_be_bytes();
+        let [c, d] = lo.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] | [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        // :: 和 ::1 也会落在这里，对应的 0.0.0.0/8 同样不是公网地址
+        [0, 0, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        [0x2002, hi, lo, ..] => Some(v4(hi, lo)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(guard: &SourceGuard, url: &str) -> Result<(), GuardError> {
+        guard.check_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn non_public_addresses_should_be_detected() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "::10.0.0.1",
+            "::7f00:1",
+            "2002:7f00:1::1",
+            "2002:a9fe:a9fe::",
+            "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+        ] {
+            assert!(
+                !is_public(ip.parse().unwrap()),
+                "{} should not be public",
+                ip
+            );
+        }
+        for ip in [
+            "8.8.8.8",
+            "1.1.1.1",
+            "2606:4700:4700::1111",
+            "::8.8.8.8",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn default_guard_should_reject_private_urls() {
+        let guard = SourceGuard::default();
+        assert!(check(&guard, "https://example.com/a.jpg").is_ok());
+        assert!(matches!(
+            check(&guard, "http://127.0.0.1/a.jpg"),
+            Err(GuardError::NonPublic(_))
+        ));
+        assert!(matches!(
+            check(&guard, "http://[::1]:8080/a.jpg"),
+            Err(GuardError::NonPublic(_))
+        ));
+        assert!(matches!(
+            check(&guard, "ftp://example.com/a.jpg"),
+            Err(GuardError::Scheme(_))
+        ));
+    }
+
+    #[test]
+    fn allow_list_should_be_respected() {
+        let guard = SourceGuard::default()
+            .allow([
+                "images.example.com",
+                "*.cdn.com",
+                "https://other.com/public/",
+                "https://static.com",
+                "https://files.com/assets",
+            ])
+            .unwrap();
+        assert!(SourceGuard::default().allow(["https://"]).is_err());
+        assert!(check(&guard, "https://images.example.com/a.jpg").is_ok());
+        assert!(check(&guard, "https://a.b.cdn.com/a.jpg").is_ok());
+        assert!(check(&guard, "https://other.com/public/a.jpg").is_ok());
+        assert!(check(&guard, "https://static.com/a/b.jpg").is_ok());
+        assert!(check(&guard, "https://STATIC.com:443/a.jpg").is_ok());
+        assert!(check(&guard, "https://files.com/assets/a.jpg").is_ok());
+        for url in [
+            "https://example.com/a.jpg",
+            "https://cdn.com/a.jpg",
+            "https://evilcdn.com/a.jpg",
+            "https://other.com/private/a.jpg",
+            "https://static.com.evil.io/a.jpg",
+            "https://static.com@evil.io/a.jpg",
+            "https://static.com:8443/a.jpg",
+            "http://static.com/a.jpg",
+            "https://files.com/assets-private/a.jpg",
+        ] {
+            assert!(
+                matches!(check(&guard, url), Err(GuardError::NotAllowed(_))),
+                "{} should not be allowed",
+                url
+            );
+        }
+    }
+}
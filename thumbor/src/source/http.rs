@@ -0,0 +1,187 @@
+use super::{FetchError, FetchLimits, Fetched, GuardError, ImageSource, SourceGuard};
+use crate::cache::ttl_from_headers;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use reqwest::{Client, Response, Url};
+use std::{error::Error, sync::Arc, time::Duration};
+
+// 通过 http(s) 下载原图，所有的地址（包括跳转之后的地址以及域名解析的结果）都会经过 SourceGuard 检查
+pub struct HttpSource {
+    guard: Arc<SourceGuard>,
+    client: Client,
+    limits: FetchLimits,
+    default_ttl: Duration,
+    max_ttl: Duration,
+}
+
+impl HttpSource {
+    pub fn new(
+        guard: SourceGuard,
+        limits: FetchLimits,
+        default_ttl: Duration,
+        max_ttl: Duration,
+    ) -> Self {
+        let guard = Arc::new(guard);
+        // 走代理时由代理解析域名，GuardedResolver 不会被调用，所以不使用系统的代理设置
+        let client = Client::builder()
+            .no_proxy()
+            .dns_resolver(Arc::new(guard.resolver()))
+            .redirect(guard.redirect_policy())
+            .build()
+            .expect("Failed to build http client");
+        Self {
+            guard,
+            client,
+            limits,
+            default_ttl,
+            max_ttl,
+        }
+    }
+
+    async fn download(&self, url: Url) -> Result<Fetched, FetchError> {
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(classify)?;
+        // 遵循上游的 Cache-Control / Expires
+        let ttl = ttl_from_headers(resp.headers(), self.default_ttl, self.max_ttl);
+        let data = read_body(resp, self.limits.max_bytes).await?;
+        Ok((data, ttl))
+    }
+}
+
+#[async_trait]
+impl ImageSource for HttpSource {
+    async fn fetch(&self, url: &Url) -> Result<Fetched, FetchError> {
+        self.guard
+            .check_url(url)
+            .map_err(|e| FetchError::Forbidden(e.to_string()))?;
+
+        let timeout = self.limits.timeout;
+        tokio::time::timeout(timeout, self.download(url.clone()))
+            .await
+            .map_err(|_| FetchError::Timeout(timeout))?
+    }
+}
+
+// 读取响应的内容，超过 max_bytes 时立即中止
+pub(super) async fn read_body(mut resp: Response, max_bytes: u64) -> Result<Bytes, FetchError> {
+    // 上游给出了长度的话，不用下载就可以拒绝
+    let len = resp.content_length().unwrap_or(0);
+    if len > max_bytes {
+        return Err(FetchError::TooLarge(max_bytes));
+    }
+
+    // 长度可能不准确或者没有给出，边下载边检查
+    let mut buf = BytesMut::with_capacity(len as usize);
+    while let Some(chunk) = resp.chunk().await.map_err(classify)? {
+        if (buf.len() + chunk.len()) as u64 > max_bytes {
+            return Err(FetchError::TooLarge(max_bytes));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+// 跳转和域名解析时被拒绝的错误包在 reqwest::Error 里面，需要沿着 source 链找出来
+pub(super) fn classify(e: reqwest::Error) -> FetchError {
+    let mut source = e.source();
+    while let Some(err) = source {
+        if let Some(guard) = err.downcast_ref::<GuardError>() {
+            return FetchError::Forbidden(guard.to_string());
+        }
+        source = err.source();
+    }
+    FetchError::Upstream(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    // 一个只会返回固定内容的 http 服务，delay 之后才开始响应
+    async fn serve(response: Vec<u8>, delay: Duration) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let response = response.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    tokio::time::sleep(delay).await;
+                    let _ = socket.write_all(&response).await;
+                });
+            }
+        });
+        Url::parse(&format!("http://{}/a.jpg", addr)).unwrap()
+    }
+
+    fn response(body: &[u8], content_length: bool) -> Vec<u8> {
+        let mut resp = b"HTTP/1.1 200 OK\r\nconnection: close\r\n".to_vec();
+        if content_length {
+            resp.extend_from_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+        }
+        resp.extend_from_slice(b"\r\n");
+        resp.extend_from_slice(body);
+        resp
+    }
+
+    // 测试服务在 127.0.0.1 上，需要允许访问内网地址
+    fn source(max_bytes: u64, timeout: Duration) -> HttpSource {
+        let guard = SourceGuard {
+            allow_private: true,
+            ..Default::default()
+        };
+        let limits = FetchLimits { max_bytes, timeout };
+        HttpSource::new(guard, limits, TTL, TTL)
+    }
+
+    #[tokio::test]
+    async fn small_source_should_be_fetched() {
+        let url = serve(response(b"hello", true), Duration::ZERO).await;
+        let (data, _) = source(5, TTL).fetch(&url).await.unwrap();
+        assert_eq!(data, Bytes::from_static(b"hello"));
+    }
+
+    #[tokio::test]
+    async fn large_source_should_be_rejected() {
+        let body = vec![0; 1024];
+        for content_length in [true, false] {
+            let url = serve(response(&body, content_length), Duration::ZERO).await;
+            assert!(matches!(
+                source(100, TTL).fetch(&url).await,
+                Err(FetchError::TooLarge(100))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_source_should_time_out() {
+        let url = serve(response(b"hello", true), Duration::from_secs(5)).await;
+        let timeout = Duration::from_millis(100);
+        assert!(matches!(
+            source(100, timeout).fetch(&url).await,
+            Err(FetchError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn private_source_should_be_forbidden_by_default() {
+        let url = serve(response(b"hello", true), Duration::ZERO).await;
+        let source = HttpSource::new(SourceGuard::default(), FetchLimits::default(), TTL, TTL);
+        assert!(matches!(
+            source.fetch(&url).await,
+            Err(FetchError::Forbidden(_))
+        ));
+    }
+}
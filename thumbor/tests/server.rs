@@ -0,0 +1,558 @@
+use axum::{handler::get, Router};
+use bytes::Bytes;
+use image::GenericImageView;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use thumbor::{build_router, config::S3Section, pb::*, signing::Signer, Config};
+
+// 1280x1280 的 png
+const LOGO: &[u8] = include_bytes!("../rust-logo.png");
+
+// 模拟的上游服务，返回 fixture 图片并记录被访问的次数
+async fn spawn_origin() -> (SocketAddr, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let no_store_counter = hits.clone();
+    let app = Router::new()
+        .route(
+            "/logo.png",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { Bytes::from_static(LOGO) }
+            }),
+        )
+        // 很慢才返回的原图
+        .route(
+            "/slow.png",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                Bytes::from_static(LOGO)
+            }),
+        )
+        // 不允许缓存的原图
+        .route(
+            "/no-store.png",
+            get(move || {
+                no_store_counter.fetch_add(1, Ordering::SeqCst);
+                async {
+                    let mut headers = HeaderMap::new();
+                    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+                    (headers, Bytes::from_static(LOGO))
+                }
+            }),
+        );
+    let server = axum::Server::bind(&([127, 0, 0, 1], 0).into()).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, hits)
+}
+
+async fn spawn_thumbor(config: Config) -> SocketAddr {
+    let app = build_router(config).unwrap();
+    let server = axum::Server::bind(&([127, 0, 0, 1], 0).into()).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+// 上游服务在 127.0.0.1 上，需要允许访问内网地址
+fn test_config() -> Config {
+    let mut config = Config::default();
+    config.source.allow_private = true;
+    config
+}
+
+fn image_url(thumbor: SocketAddr, specs: Vec<Spec>, origin: &str) -> String {
+    let spec = String::from(&ImageSpec::new(specs));
+    let origin = percent_encode(origin.as_bytes(), NON_ALPHANUMERIC);
+    format!("http://{}/image/{}/{}", thumbor, spec, origin)
+}
+
+async fn get_image(url: &str, accept: Option<&str>) -> reqwest::Response {
+    let mut req = reqwest::Client::new().get(url);
+    if let Some(accept) = accept {
+        req = req.header(header::ACCEPT, accept);
+    }
+    req.send().await.unwrap()
+}
+
+#[derive(Deserialize)]
+struct Stats {
+    thumbnail: TierStats,
+}
+
+#[derive(Deserialize)]
+struct TierStats {
+    memory: CacheStats,
+}
+
+#[derive(Deserialize)]
+struct CacheStats {
+    hits: u64,
+}
+
+#[tokio::test]
+async fn resize_should_return_image_with_requested_size() {
+    let (origin, _) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let specs = vec![
+        Spec::new_resize(100, 50, resize::SampleFilter::Nearest),
+        Spec::new_format(format::Type::Png, 0),
+    ];
+    let url = image_url(thumbor, specs, &format!("http://{}/logo.png", origin));
+
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    assert_eq!(resp.headers()[header::VARY], "accept");
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (100, 50));
+}
+
+#[tokio::test]
+async fn accept_header_should_choose_output_format() {
+    let (origin, _) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let url = image_url(thumbor, specs, &format!("http://{}/logo.png", origin));
+
+    let resp = get_image(&url, Some("image/webp,image/*;q=0.8")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/webp");
+    assert!(resp.bytes().await.unwrap().starts_with(b"RIFF"));
+
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/jpeg");
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (64, 64));
+}
+
+#[tokio::test]
+async fn invalid_requests_should_be_rejected() {
+    let (origin, _) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let logo = format!("http://{}/logo.png", origin);
+
+    let encoded = percent_encode(logo.as_bytes(), NON_ALPHANUMERIC);
+    let url = format!("http://{}/image/not-a-spec/{}", thumbor, encoded);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let missing = format!("http://{}/missing.png", origin);
+    let url = image_url(thumbor, specs, &missing);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let crop = Spec {
+        data: Some(spec::Data::Crop(Crop {
+            x1: 0,
+            y1: 0,
+            x2: 2000,
+            y2: 2000,
+        })),
+    };
+    let url = image_url(thumbor, vec![crop], &logo);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    // 处理步骤太多
+    let specs = vec![Spec::new_blur(100); 33];
+    let url = image_url(thumbor, specs, &logo);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn watermark_from_url_should_be_applied() {
+    let (origin, _) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let logo = format!("http://{}/logo.png", origin);
+    let mark = |url: &str| Spec {
+        data: Some(spec::Data::Watermark(Watermark {
+            x: 8,
+            y: 8,
+            source: Some(watermark::Source::Url(url.to_owned())),
+            opacity: 0.5,
+            scale: 0.25,
+            placement: watermark::Placement::Anchored as i32,
+            gravity: Gravity::SouthEast as i32,
+        })),
+    };
+    let resize = Spec::new_resize(200, 200, resize::SampleFilter::Nearest);
+
+    let url = image_url(thumbor, vec![resize.clone()], &logo);
+    let plain = get_image(&url, None).await.bytes().await.unwrap();
+    let plain = image::load_from_memory(&plain).unwrap();
+    let url = image_url(thumbor, vec![resize.clone(), mark(&logo)], &logo);
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (200, 200));
+
+    // 50x50 的水印放在右下角，距离边缘 8 个像素，左上角保持不变
+    let differs = |x0: u32, y0: u32| {
+        (x0..x0 + 50)
+            .flat_map(|x| (y0..y0 + 50).map(move |y| (x, y)))
+            .any(|(x, y)| image.get_pixel(x, y) != plain.get_pixel(x, y))
+    };
+    assert!(differs(142, 142));
+    assert!(!differs(0, 0));
+
+    // 重复的 url 只下载一次，不计入数量限制
+    let specs = std::iter::once(resize)
+        .chain((0..5).map(|_| mark(&logo)))
+        .collect();
+    let url = image_url(thumbor, specs, &logo);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::OK);
+
+    // 不同的 url 超过数量限制
+    let specs = (0..5).map(|i| mark(&format!("{}?{}", logo, i))).collect();
+    let url = image_url(thumbor, specs, &logo);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    // 未注册的水印名字是无效的 spec
+    let mark = Spec {
+        data: Some(spec::Data::Watermark(Watermark {
+            source: Some(watermark::Source::Name("missing".to_owned())),
+            ..Default::default()
+        })),
+    };
+    let url = image_url(thumbor, vec![mark], &logo);
+    assert_eq!(
+        get_image(&url, None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn presets_should_expand_with_inline_specs() {
+    let (origin, _) = spawn_origin().await;
+    let mut config = test_config();
+    config.presets.insert(
+        "avatar".to_owned(),
+        "resize(64,64,fit=cover)|format(png)".to_owned(),
+    );
+    let thumbor = spawn_thumbor(config).await;
+    let origin = percent_encode(
+        format!("http://{}/logo.png", origin).as_bytes(),
+        NON_ALPHANUMERIC,
+    )
+    .to_string();
+    let url = |spec: &str| {
+        let spec = percent_encode(spec.as_bytes(), NON_ALPHANUMERIC);
+        format!("http://{}/image/{}/{}", thumbor, spec, origin)
+    };
+
+    let resp = get_image(&url("preset:avatar"), None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (64, 64));
+
+    // preset 后面可以继续接其他 spec
+    let resp = get_image(&url("preset:avatar|resize(32,16)"), None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (32, 16));
+
+    assert_eq!(
+        get_image(&url("preset:missing"), None).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn uploaded_image_should_be_processed() {
+    let thumbor = spawn_thumbor(test_config()).await;
+    let image_spec = ImageSpec::new(vec![
+        Spec::new_resize(40, 30, resize::SampleFilter::Nearest),
+        Spec::new_format(format::Type::Png, 0),
+    ]);
+    let spec = serde_json::to_string(&image_spec).unwrap();
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/process", thumbor);
+
+    // 请求体就是图片，spec 放在 query 里
+    let resp = client
+        .post(&url)
+        .query(&[("spec", &spec)])
+        .header(header::CONTENT_TYPE, "image/png")
+        .body(LOGO)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (40, 30));
+
+    // multipart 里同时有图片和 spec
+    let boundary = "thumbor-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"spec\"\r\n\r\n{}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"logo.png\"\r\n\
+             Content-Type: image/png\r\n\r\n",
+            spec,
+            b = boundary
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(LOGO);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let resp = client
+        .post(&url)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (40, 30));
+
+    // 没有 spec 或者图片太大都会被拒绝
+    let resp = client.post(&url).body(LOGO).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // 图片头完整但是数据被截断
+    let resp = client
+        .post(&url)
+        .query(&[("spec", &spec)])
+        .body(&LOGO[..LOGO.len() / 2])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let mut config = test_config();
+    config.source.max_bytes = 1024;
+    let thumbor = spawn_thumbor(config).await;
+    let resp = client
+        .post(format!("http://{}/process", thumbor))
+        .query(&[("spec", &spec)])
+        .body(LOGO)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn upload_should_be_forbidden_with_signing_key() {
+    let mut config = test_config();
+    config.server.signing_key = Some("secret".to_owned());
+    let thumbor = spawn_thumbor(config).await;
+    let specs = vec![Spec::new_resize(40, 30, resize::SampleFilter::Nearest)];
+    let spec = serde_json::to_string(&ImageSpec::new(specs)).unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/process", thumbor))
+        .query(&[("spec", &spec)])
+        .body(LOGO)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[derive(Deserialize)]
+struct Info {
+    width: u32,
+    height: u32,
+    format: String,
+    bytes: usize,
+    alpha: bool,
+    palette: Vec<PaletteColor>,
+}
+
+#[derive(Deserialize)]
+struct PaletteColor {
+    color: String,
+}
+
+#[tokio::test]
+async fn info_should_describe_source_image() {
+    let (origin, hits) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let origin = percent_encode(
+        format!("http://{}/logo.png", origin).as_bytes(),
+        NON_ALPHANUMERIC,
+    );
+    let url = format!("http://{}/info/{}", thumbor, origin);
+
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: Info = resp.json().await.unwrap();
+    assert_eq!((info.width, info.height), (1280, 1280));
+    assert_eq!(info.format, "png");
+    assert_eq!(info.bytes, LOGO.len());
+    assert!(info.alpha);
+    assert!(!info.palette.is_empty());
+    assert!(info.palette[0].color.starts_with('#'));
+
+    // 和生成缩略图共用原图的缓存
+    get_image(&url, None).await;
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn only_signed_info_should_be_accepted_with_signing_key() {
+    let (origin, _) = spawn_origin().await;
+    let mut config = test_config();
+    config.server.signing_key = Some("secret".to_owned());
+    let thumbor = spawn_thumbor(config).await;
+    let logo = format!("http://{}/logo.png", origin);
+
+    let origin = percent_encode(logo.as_bytes(), NON_ALPHANUMERIC);
+    let url = format!("http://{}/info/{}", thumbor, origin);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+
+    let path = Signer::new("secret").signed_info_path(&logo);
+    let resp = get_image(&format!("http://{}{}", thumbor, path), None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: Info = resp.json().await.unwrap();
+    assert_eq!((info.width, info.height), (1280, 1280));
+
+    // 其他 key 的签名，以及生成缩略图的签名都不能用于 /info
+    let path = Signer::new("other").signed_info_path(&logo);
+    let url = format!("http://{}{}", thumbor, path);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+    let signature = Signer::new("secret").sign("resize(64,64)", &logo);
+    let url = format!("http://{}/info/{}/{}", thumbor, signature, origin);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn private_origin_should_be_forbidden_by_default() {
+    let (origin, hits) = spawn_origin().await;
+    let thumbor = spawn_thumbor(Config::default()).await;
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let url = image_url(thumbor, specs, &format!("http://{}/logo.png", origin));
+
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn only_signed_urls_should_be_accepted_with_signing_key() {
+    let (origin, _) = spawn_origin().await;
+    let mut config = test_config();
+    config.server.signing_key = Some("secret".to_owned());
+    let thumbor = spawn_thumbor(config).await;
+
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let spec = String::from(&ImageSpec::new(specs.clone()));
+    let logo = format!("http://{}/logo.png", origin);
+
+    let url = image_url(thumbor, specs, &logo);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+
+    let path = Signer::new("secret").signed_path(&spec, &logo);
+    let url = format!("http://{}{}", thumbor, path);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::OK);
+
+    let path = Signer::new("other").signed_path(&spec, &logo);
+    let url = format!("http://{}{}", thumbor, path);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+
+    // 文本形式的 spec 里有引号和 `/`，签名针对的是转义之前的内容
+    let spec = format!(r#"resize(64,64)|watermark(url="{}",scale=0.5)"#, logo);
+    let path = Signer::new("secret").signed_path(&spec, &logo);
+    let url = format!("http://{}{}", thumbor, path);
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let image = image::load_from_memory(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(image.dimensions(), (64, 64));
+
+    // 换掉签名过的 spec
+    let encode = |s: &str| percent_encode(s.as_bytes(), NON_ALPHANUMERIC).to_string();
+    let tampered = spec.replace("scale=0.5", "scale=1");
+    let path = Signer::new("secret").signed_path(&spec, &logo);
+    let path = path.replace(&encode(&spec), &encode(&tampered));
+    let url = format!("http://{}{}", thumbor, path);
+    assert_eq!(get_image(&url, None).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn slow_request_should_time_out() {
+    let (origin, _) = spawn_origin().await;
+    let mut config = test_config();
+    config.server.request_timeout = 1;
+    let thumbor = spawn_thumbor(config).await;
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let url = image_url(thumbor, specs, &format!("http://{}/slow.png", origin));
+
+    let resp = get_image(&url, None).await;
+    assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[tokio::test]
+async fn repeated_request_should_be_served_from_cache() {
+    let (origin, hits) = spawn_origin().await;
+    let thumbor = spawn_thumbor(test_config()).await;
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let url = image_url(thumbor, specs, &format!("http://{}/logo.png", origin));
+
+    let first = get_image(&url, None).await.bytes().await.unwrap();
+    let second = get_image(&url, None).await.bytes().await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    let stats: Stats = reqwest::get(format!("http://{}/stats", thumbor))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats.thumbnail.memory.hits, 1);
+
+    // 原图不允许缓存时，缩略图也不缓存
+    let specs = vec![Spec::new_resize(64, 64, resize::SampleFilter::Nearest)];
+    let url = image_url(thumbor, specs, &format!("http://{}/no-store.png", origin));
+    get_image(&url, None).await;
+    get_image(&url, None).await;
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn invalid_config_should_be_rejected_by_router() {
+    let mut config = test_config();
+    config.source.s3 = Some(S3Section {
+        endpoint: "not a url".to_owned(),
+        region: "us-east-1".to_owned(),
+        access_key: String::new(),
+        secret_key: String::new(),
+        path_style: false,
+        buckets: vec!["photos".to_owned()],
+    });
+    assert!(build_router(config).is_err());
+}